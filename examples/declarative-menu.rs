@@ -0,0 +1,42 @@
+// Rebuilds the whole menu from app state on every change, letting set_menu
+// work out what actually needs to be updated.
+use systray::{Application, Icon, Menu, MenuItem};
+
+fn build_menu(syncing: bool) -> Menu {
+    Menu::new()
+        .item(
+            MenuItem::new(if syncing { "Syncing..." } else { "Sync now" })
+                .id("sync")
+                .enabled(!syncing)
+                .on_activate(|app: &mut Application| app.set_menu(build_menu(true))),
+        )
+        .item(
+            MenuItem::new("Sync finished")
+                .enabled(syncing)
+                .on_activate(|app: &mut Application| app.set_menu(build_menu(false))),
+        )
+        .item(MenuItem::separator())
+        .item(MenuItem::submenu(
+            "Mode",
+            Menu::new()
+                .item(MenuItem::radio("Fast", "mode", false))
+                .item(MenuItem::radio("Balanced", "mode", true))
+                .item(MenuItem::radio("Quiet", "mode", false)),
+        ))
+        .item(MenuItem::separator())
+        .item(MenuItem::new("Quit").on_activate(|app: &mut Application| {
+            app.quit();
+            Ok::<_, systray::Error>(())
+        }))
+}
+
+fn main() -> Result<(), systray::Error> {
+    let mut app = Application::builder()
+        .id("systray-declarative-menu")
+        .title("Declarative menu")
+        .icon(Icon::from_bytes(include_bytes!("../resources/rust.ico"))?)
+        .build()?;
+    app.set_menu(build_menu(false))?;
+    app.wait_for_message()?;
+    Ok(())
+}
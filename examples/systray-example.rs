@@ -10,12 +10,31 @@ fn main() -> Result<(), systray::Error> {
     // w.set_icon_from_file(&"C:\\Users\\qdot\\code\\git-projects\\systray-rs\\resources\\rust.ico".to_string());
     // w.set_tooltip(&"Whatever".to_string());
     app.set_icon_from_file("/usr/share/gxkb/flags/ua.png")?;
+    app.set_tooltip("systray-rs example")?;
 
     app.add_menu_item("Print a thing", |_| {
         println!("Printing a thing!");
         Ok::<_, systray::Error>(())
     })?;
 
+    app.add_menu_item("Red icon", |window| {
+        let red: Vec<u8> = [0xff, 0x00, 0x00, 0xff].repeat(32 * 32);
+        window.set_icon_from_rgba(&red, 32, 32)?;
+        Ok::<_, systray::Error>(())
+    })?;
+
+    app.add_menu_item("Blink", |window| {
+        let frames = [[0xff, 0x00, 0x00, 0xff], [0x00, 0x00, 0x00, 0x00]]
+            .iter()
+            .map(|pixel| systray::Icon::from_rgba(pixel.repeat(32 * 32), 32, 32))
+            .collect::<Result<Vec<_>, _>>()?;
+        window.set_animated_icon(frames, std::time::Duration::from_millis(500))?;
+        Ok::<_, systray::Error>(())
+    })?;
+
+    let rust_icon = systray::Icon::from_bytes(include_bytes!("../resources/rust.ico"))?;
+    app.add_menu_item("Rust icon", move |window| window.set_icon(&rust_icon))?;
+
     app.add_menu_item("Add Menu Item", |window| {
         window.add_menu_item("Interior item", |_| {
             println!("what");
@@ -25,6 +44,29 @@ fn main() -> Result<(), systray::Error> {
         Ok::<_, systray::Error>(())
     })?;
 
+    let profiles = app.add_submenu("Profiles")?;
+    for profile in &["Work", "Home", "Gaming"] {
+        profiles.add_menu_item(&mut app, profile, move |_| {
+            println!("Switched to {} profile", profile);
+            Ok::<_, systray::Error>(())
+        })?;
+    }
+
+    app.add_menu_separator()?;
+
+    app.add_check_menu_item("Start at login", false, |_, checked| {
+        println!("Start at login: {}", checked);
+        Ok::<_, systray::Error>(())
+    })?;
+
+    let mode = app.add_radio_group();
+    for (name, checked) in &[("Fast", false), ("Balanced", true), ("Quiet", false)] {
+        app.add_radio_menu_item(mode, name, *checked, move |_, checked| {
+            println!("Mode {} selected: {}", name, checked);
+            Ok::<_, systray::Error>(())
+        })?;
+    }
+
     app.add_menu_separator()?;
 
     app.add_menu_item("Quit", |window| {
@@ -32,6 +74,21 @@ fn main() -> Result<(), systray::Error> {
         Ok::<_, systray::Error>(())
     })?;
 
+    let uptime = app.add_menu_item("Up for 0s", |_| Ok::<_, systray::Error>(()))?;
+    app.set_menu_item_enabled(uptime, false)?;
+    let handle = app.handle();
+    std::thread::spawn(move || {
+        for secs in 1.. {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            if handle
+                .set_menu_item_label(uptime, &format!("Up for {}s", secs))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
     println!("Waiting on message!");
     app.wait_for_message()?;
     Ok(())
@@ -0,0 +1,112 @@
+// Minimal XEmbed system tray, for trying the "xembed" backend on a bare X
+// server such as Xvfb:
+//
+//     Xvfb :5 &
+//     DISPLAY=:5 cargo run --example xembed-tray-host --features xembed &
+//     DISPLAY=:5 cargo run --example systray-example --no-default-features --features xembed
+//
+// Takes the tray selection, docks every icon that asks into one bar window,
+// and prints what happens.
+
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        xproto::{
+            ClientMessageEvent, ConfigureWindowAux, ConnectionExt, CreateWindowAux, EventMask,
+            SetMode, WindowClass,
+        },
+        Event,
+    },
+    COPY_FROM_PARENT, CURRENT_TIME,
+};
+
+const ICON_SIZE: u16 = 24;
+const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+const XEMBED_EMBEDDED_NOTIFY: u32 = 0;
+
+fn intern(conn: &impl Connection, name: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let screen = conn.setup().roots[screen_num].clone();
+    let selection = intern(&conn, &format!("_NET_SYSTEM_TRAY_S{}", screen_num))?;
+    let manager = intern(&conn, "MANAGER")?;
+    let opcode = intern(&conn, "_NET_SYSTEM_TRAY_OPCODE")?;
+    let xembed = intern(&conn, "_XEMBED")?;
+
+    let bar = conn.generate_id()?;
+    conn.create_window(
+        COPY_FROM_PARENT as u8,
+        bar,
+        screen.root,
+        0,
+        0,
+        ICON_SIZE * 8,
+        ICON_SIZE,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        screen.root_visual,
+        &CreateWindowAux::new()
+            .background_pixel(screen.white_pixel)
+            .event_mask(EventMask::SUBSTRUCTURE_NOTIFY),
+    )?;
+    conn.map_window(bar)?;
+
+    conn.set_selection_owner(bar, selection, CURRENT_TIME)?;
+    if conn.get_selection_owner(selection)?.reply()?.owner != bar {
+        return Err("Another system tray is running".into());
+    }
+    let announce = ClientMessageEvent::new(
+        32,
+        screen.root,
+        manager,
+        [CURRENT_TIME, selection, bar, 0, 0],
+    );
+    conn.send_event(false, screen.root, EventMask::STRUCTURE_NOTIFY, announce)?;
+    conn.flush()?;
+    println!("Tray running on screen {}", screen_num);
+
+    let mut icons: Vec<u32> = vec![];
+    loop {
+        match conn.wait_for_event()? {
+            Event::ClientMessage(e)
+                if e.type_ == opcode && e.data.as_data32()[1] == SYSTEM_TRAY_REQUEST_DOCK =>
+            {
+                let icon = e.data.as_data32()[2];
+                let x = (icons.len() as u16 * ICON_SIZE) as i16;
+                // Keep the icon alive if we go away first.
+                conn.change_save_set(SetMode::INSERT, icon)?;
+                conn.reparent_window(icon, bar, x, 0)?;
+                conn.configure_window(
+                    icon,
+                    &ConfigureWindowAux::new()
+                        .width(u32::from(ICON_SIZE))
+                        .height(u32::from(ICON_SIZE)),
+                )?;
+                conn.map_window(icon)?;
+                let notify = ClientMessageEvent::new(
+                    32,
+                    icon,
+                    xembed,
+                    [CURRENT_TIME, XEMBED_EMBEDDED_NOTIFY, 0, bar, 0],
+                );
+                conn.send_event(false, icon, EventMask::NO_EVENT, notify)?;
+                conn.flush()?;
+                icons.push(icon);
+                println!("Docked icon {:#x}", icon);
+            }
+            Event::DestroyNotify(e) if icons.contains(&e.window) => {
+                icons.retain(|&i| i != e.window);
+                println!("Icon {:#x} went away", e.window);
+            }
+            Event::ReparentNotify(e) if icons.contains(&e.window) && e.parent != bar => {
+                icons.retain(|&i| i != e.window);
+                println!("Icon {:#x} left the tray", e.window);
+            }
+            Event::Error(e) => println!("X11 error: {:?}", e),
+            _ => {}
+        }
+    }
+}
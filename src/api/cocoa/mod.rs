@@ -1,28 +1,29 @@
 use crate::Error;
 use std;
 
+// Nothing is implemented for macOS yet.
+const UNSUPPORTED: Error = Error::Unsupported {
+    feature: "tray icons on macOS",
+};
+
 pub struct Window {}
 
 impl Window {
     pub fn new() -> Result<Window, Error> {
-        Err(Error::NotImplementedError)
-    }
-    pub fn quit(&self) {
-        unimplemented!()
+        Err(UNSUPPORTED)
     }
+    pub fn quit(&self) {}
     pub fn set_tooltip(&self, _: &str) -> Result<(), Error> {
-        unimplemented!()
+        Err(UNSUPPORTED)
     }
     pub fn add_menu_item<F>(&self, _: &str, _: F) -> Result<u32, Error>
     where
         F: std::ops::Fn(&Window) -> () + 'static,
     {
-        unimplemented!()
-    }
-    pub fn wait_for_message(&mut self) {
-        unimplemented!()
+        Err(UNSUPPORTED)
     }
+    pub fn wait_for_message(&mut self) {}
     pub fn set_icon_from_buffer(&self, _: &[u8], _: u32, _: u32) -> Result<(), Error> {
-        unimplemented!()
+        Err(UNSUPPORTED)
     }
 }
@@ -0,0 +1,112 @@
+// Thin wrapper around the AppIndicator GObject. The libappindicator crate
+// keeps the object pointer to itself, and we need it to connect to the
+// indicator's signals.
+
+use glib::{
+    gobject_sys,
+    translate::{FromGlibPtrFull, ToGlibPtr},
+    ObjectExt, ObjectType,
+};
+use libappindicator::{AppIndicatorCategory, AppIndicatorStatus};
+
+// GdkScrollDirection values passed along with "scroll-event".
+pub const SCROLL_UP: i32 = 0;
+pub const SCROLL_DOWN: i32 = 1;
+pub const SCROLL_LEFT: i32 = 2;
+pub const SCROLL_RIGHT: i32 = 3;
+
+pub struct Indicator {
+    obj: glib::Object,
+}
+
+impl Indicator {
+    pub fn new(id: &str, icon: &str, category: AppIndicatorCategory) -> Indicator {
+        unsafe {
+            let ptr = libappindicator::app_indicator_new(
+                id.to_glib_none().0,
+                icon.to_glib_none().0,
+                category as u32,
+            );
+            Indicator {
+                obj: glib::Object::from_glib_full(ptr as *mut gobject_sys::GObject),
+            }
+        }
+    }
+
+    pub fn set_status(&self, status: AppIndicatorStatus) {
+        unsafe {
+            libappindicator::app_indicator_set_status(self.obj.as_ptr() as *mut _, status as u32);
+        }
+    }
+
+    pub fn set_menu(&self, menu: &gtk::Menu) {
+        unsafe {
+            libappindicator::app_indicator_set_menu(
+                self.obj.as_ptr() as *mut _,
+                menu.to_glib_none().0,
+            );
+        }
+    }
+
+    pub fn set_icon_full(&self, name: &str, desc: &str) {
+        unsafe {
+            libappindicator::app_indicator_set_icon_full(
+                self.obj.as_ptr() as *mut _,
+                name.to_glib_none().0,
+                desc.to_glib_none().0,
+            );
+        }
+    }
+
+    pub fn set_attention_icon_full(&self, name: &str, desc: &str) {
+        unsafe {
+            libappindicator::app_indicator_set_attention_icon_full(
+                self.obj.as_ptr() as *mut _,
+                name.to_glib_none().0,
+                desc.to_glib_none().0,
+            );
+        }
+    }
+
+    pub fn set_label(&self, label: &str, guide: &str) {
+        unsafe {
+            libappindicator::app_indicator_set_label(
+                self.obj.as_ptr() as *mut _,
+                label.to_glib_none().0,
+                guide.to_glib_none().0,
+            );
+        }
+    }
+
+    pub fn set_icon_theme_path(&self, path: &str) {
+        unsafe {
+            libappindicator::app_indicator_set_icon_theme_path(
+                self.obj.as_ptr() as *mut _,
+                path.to_glib_none().0,
+            );
+        }
+    }
+
+    // Shown as the tooltip by most StatusNotifierItem hosts.
+    pub fn set_title(&self, title: &str) {
+        unsafe {
+            libappindicator::app_indicator_set_title(
+                self.obj.as_ptr() as *mut _,
+                title.to_glib_none().0,
+            );
+        }
+    }
+
+    // Calls f with the scroll delta and one of the SCROLL_* directions.
+    pub fn connect_scroll_event<F: Fn(i32, i32) + 'static>(&self, f: F) {
+        self.obj
+            .connect_local("scroll-event", false, move |values| {
+                let delta = values[1].get_some::<i32>().unwrap_or(0);
+                let direction =
+                    unsafe { gobject_sys::g_value_get_enum(values[2].to_glib_none().0) };
+                f(delta, direction);
+                None
+            })
+            .ok();
+    }
+}
@@ -1,24 +1,60 @@
-use crate::{Error, SystrayEvent};
-use glib;
+mod indicator;
+
+use self::indicator::Indicator;
+#[cfg(feature = "dbus")]
+use super::watcher;
+use super::{notifications::Notifier, Backend, BackendHandle};
+use crate::{
+    icon::IconImage, ApplicationBuilder, Category, Error, Event, EventSender, Icon, Notification,
+    ScrollOrientation, Status,
+};
+use glib::{
+    self,
+    object::{Cast, IsA},
+    ObjectExt,
+};
 use gtk::{
-    self, MenuShellExt, GtkMenuItemExt, WidgetExt
+    self, CheckMenuItemExt, MenuShellExt, GtkMenuItemExt, WidgetExt
 };
-use libappindicator::{AppIndicator, AppIndicatorStatus};
+use gdk_pixbuf::{Colorspace, Pixbuf};
+use libappindicator::{AppIndicatorCategory, AppIndicatorStatus};
 use std::{
     self,
     cell::RefCell,
     collections::HashMap,
-    sync::mpsc::{channel, Sender},
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, RecvTimeoutError},
+        Arc,
+    },
     thread,
+    time::Duration,
 };
 
 // Gtk specific struct that will live only in the Gtk thread, since a lot of the
 // base types involved don't implement Send (for good reason).
 pub struct GtkSystrayApp {
     menu: gtk::Menu,
-    ai: RefCell<AppIndicator>,
+    ai: Indicator,
     menu_items: RefCell<HashMap<u32, gtk::MenuItem>>,
-    event_tx: Sender<SystrayEvent>,
+    submenus: RefCell<HashMap<u32, gtk::Menu>>,
+    // Activate handlers of check items, so they can be blocked while we
+    // update the check state from the library side.
+    check_handlers: RefCell<HashMap<u32, glib::SignalHandlerId>>,
+    // Icon file written by set_icon, removed once it is replaced.
+    icon_file: RefCell<Option<PathBuf>>,
+    animation: RefCell<Option<Animation>>,
+    attention_file: RefCell<Option<PathBuf>>,
+    event_tx: EventSender,
+}
+
+// Icon animation, with every frame written to a file up front.
+struct Animation {
+    source: glib::SourceId,
+    files: Vec<PathBuf>,
+    frame: usize,
 }
 
 thread_local!(static GTK_STASH: RefCell<Option<GtkSystrayApp>> = RefCell::new(None));
@@ -33,6 +69,46 @@ pub struct MenuItemInfo {
 
 type Callback = Box<(Fn(&GtkSystrayApp) -> () + 'static)>;
 
+// Appends the item, or inserts it at the given position of the menu.
+fn insert_into_menu<P: IsA<gtk::MenuItem> + IsA<gtk::Widget>>(
+    menu: &gtk::Menu,
+    item: &P,
+    position: Option<u32>,
+) {
+    match position {
+        Some(p) => menu.insert(item, p as i32),
+        None => menu.append(item),
+    }
+}
+
+// Panels scale file icons to fit, so use a size that still looks sharp on
+// large or HiDPI panels.
+const ICON_FILE_SIZE: u32 = 64;
+
+// AppIndicator only takes icons by name or path, and caches them by path, so
+// every image is written to a new file.
+fn write_icon_file(image: &IconImage) -> Result<PathBuf, Error> {
+    static ICON_COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "systray-{}-{}.png",
+        std::process::id(),
+        ICON_COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let pixbuf = Pixbuf::new_from_mut_slice(
+        image.rgba.clone(),
+        Colorspace::Rgb,
+        true,
+        8,
+        image.width as i32,
+        image.height as i32,
+        image.width as i32 * 4,
+    );
+    pixbuf
+        .savev(&path, "png", &[])
+        .map_err(|e| Error::OsError(format!("Error writing icon file: {}", e)))?;
+    Ok(path)
+}
+
 // Convenience function to clean up thread local unwrapping
 fn run_on_gtk_thread<F>(f: F)
 where
@@ -53,127 +129,671 @@ where
 }
 
 impl GtkSystrayApp {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<GtkSystrayApp, Error> {
-        if let Err(e) = gtk::init() {
-            return Err(Error::OsError(format!("{}", "Gtk init error!")));
+    pub fn new(event_tx: EventSender, config: ApplicationBuilder) -> Result<GtkSystrayApp, Error> {
+        gtk::init().map_err(|e| Error::BackendInit {
+            source: Box::new(e),
+        })?;
+        let m = gtk::Menu::new();
+        let mut icon_file = None;
+        let icon = match (config.icon_name, config.icon) {
+            (Some(name), _) => name,
+            (None, Some(icon)) => {
+                let path = write_icon_file(icon.best_for_size(ICON_FILE_SIZE))?;
+                let icon = path.to_string_lossy().into_owned();
+                icon_file = Some(path);
+                icon
+            }
+            (None, None) => String::new(),
+        };
+        let category = match config.category {
+            Category::ApplicationStatus => AppIndicatorCategory::ApplicationStatus,
+            Category::Communications => AppIndicatorCategory::Communications,
+            Category::SystemServices => AppIndicatorCategory::SystemServices,
+            Category::Hardware => AppIndicatorCategory::Hardware,
+        };
+        let ai = Indicator::new(&config.id, &icon, category);
+        // The indicator only shows up on the bus once it is active.
+        if let Some(title) = config.title {
+            ai.set_title(&title);
         }
-        let mut m = gtk::Menu::new();
-        let mut ai = AppIndicator::new("", "");
         ai.set_status(AppIndicatorStatus::Active);
-        ai.set_menu(&mut m);
+        // The panel draws the menu from the copy exported over dbusmenu,
+        // showing and hiding this one has nothing to do with the user opening
+        // it, so there are no MenuOpened and MenuClosed events.
+        ai.set_menu(&m);
+        ai.connect_scroll_event(|delta, direction| {
+            let (delta, orientation) = match direction {
+                indicator::SCROLL_UP => (-delta, ScrollOrientation::Vertical),
+                indicator::SCROLL_DOWN => (delta, ScrollOrientation::Vertical),
+                indicator::SCROLL_LEFT => (-delta, ScrollOrientation::Horizontal),
+                indicator::SCROLL_RIGHT => (delta, ScrollOrientation::Horizontal),
+                _ => return,
+            };
+            run_on_gtk_thread(move |stash: &GtkSystrayApp| {
+                stash.send_event(Event::Scroll { delta, orientation });
+            });
+        });
         Ok(GtkSystrayApp {
             menu: m,
-            ai: RefCell::new(ai),
+            ai,
             menu_items: RefCell::new(HashMap::new()),
+            submenus: RefCell::new(HashMap::new()),
+            check_handlers: RefCell::new(HashMap::new()),
+            icon_file: RefCell::new(icon_file),
+            animation: RefCell::new(None),
+            attention_file: RefCell::new(None),
             event_tx: event_tx,
         })
     }
 
+    pub fn send_event(&self, event: Event) {
+        self.event_tx.send(event);
+    }
+
     pub fn systray_menu_selected(&self, menu_id: u32) {
-        self.event_tx
-            .send(SystrayEvent {
-                menu_index: menu_id as u32,
-            })
-            .ok();
+        self.send_event(Event::MenuItemActivated { index: menu_id });
     }
 
-    pub fn add_menu_separator(&self, item_idx: u32) {
-        //let mut menu_items = self.menu_items.borrow_mut();
+    // Returns the menu shell that entries with the given parent should be
+    // appended to. Top level entries (no parent) go into the indicator menu.
+    fn parent_menu(&self, parent: Option<u32>) -> Result<gtk::Menu, Error> {
+        match parent {
+            None => Ok(self.menu.clone()),
+            Some(p) => self
+                .submenus
+                .borrow()
+                .get(&p)
+                .cloned()
+                .ok_or(Error::InvalidMenuItem(p)),
+        }
+    }
+
+    pub fn add_menu_separator(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+    ) -> Result<(), Error> {
+        let mut menu_items = self.menu_items.borrow_mut();
+        let menu = self.parent_menu(parent)?;
         let m = gtk::SeparatorMenuItem::new();
-        self.menu.append(&m);
-        //menu_items.insert(item_idx, m);
-        self.menu.show_all();
+        insert_into_menu(&menu, &m, position);
+        menu_items.insert(item_idx, m.upcast());
+        menu.show_all();
+        Ok(())
     }
 
-    pub fn add_menu_entry(&self, item_idx: u32, item_name: &str) {
+    pub fn add_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
         let mut menu_items = self.menu_items.borrow_mut();
-        if menu_items.contains_key(&item_idx) {
-            let m: &gtk::MenuItem = menu_items.get(&item_idx).unwrap();
+        if let Some(m) = menu_items.get(&item_idx) {
             m.set_label(item_name);
             self.menu.show_all();
-            return;
+            return Ok(());
         }
+        let menu = self.parent_menu(parent)?;
         let m = gtk::MenuItem::new_with_label(item_name);
-        self.menu.append(&m);
+        insert_into_menu(&menu, &m, position);
         m.connect_activate(move |_| {
             run_on_gtk_thread(move |stash: &GtkSystrayApp| {
                 stash.systray_menu_selected(item_idx);
             });
         });
         menu_items.insert(item_idx, m);
-        self.menu.show_all();
+        menu.show_all();
+        Ok(())
     }
 
-    pub fn set_icon_from_file(&self, file: &str) {
-        let mut ai = self.ai.borrow_mut();
-        ai.set_icon_full(file, "icon");
+    pub fn add_submenu(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        let menu = self.parent_menu(parent)?;
+        let m = gtk::MenuItem::new_with_label(item_name);
+        let submenu = gtk::Menu::new();
+        m.set_submenu(Some(&submenu));
+        insert_into_menu(&menu, &m, position);
+        self.menu_items.borrow_mut().insert(item_idx, m);
+        self.submenus.borrow_mut().insert(item_idx, submenu);
+        menu.show_all();
+        Ok(())
+    }
+
+    pub fn add_check_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+        checked: bool,
+        radio: bool,
+    ) -> Result<(), Error> {
+        let menu = self.parent_menu(parent)?;
+        // Radio items are plain check items drawn as radio buttons. Group
+        // exclusivity is kept by the library, so we don't use GtkRadioMenuItem
+        // groups here.
+        let m = gtk::CheckMenuItem::new_with_label(item_name);
+        m.set_draw_as_radio(radio);
+        m.set_active(checked);
+        insert_into_menu(&menu, &m, position);
+        let handler = m.connect_activate(move |_| {
+            run_on_gtk_thread(move |stash: &GtkSystrayApp| {
+                stash.systray_menu_selected(item_idx);
+            });
+        });
+        self.check_handlers.borrow_mut().insert(item_idx, handler);
+        self.menu_items.borrow_mut().insert(item_idx, m.upcast());
+        menu.show_all();
+        Ok(())
+    }
+
+    pub fn set_menu_entry_checked(&self, item_idx: u32, checked: bool) -> Result<(), Error> {
+        let menu_items = self.menu_items.borrow();
+        let check_handlers = self.check_handlers.borrow();
+        let m = match menu_items
+            .get(&item_idx)
+            .and_then(|m| m.downcast_ref::<gtk::CheckMenuItem>())
+        {
+            Some(m) => m,
+            None => return Err(Error::InvalidMenuItem(item_idx)),
+        };
+        // gtk_check_menu_item_set_active emits "activate", which would be
+        // reported back to us as a click.
+        if let Some(handler) = check_handlers.get(&item_idx) {
+            m.block_signal(handler);
+            m.set_active(checked);
+            m.unblock_signal(handler);
+        } else {
+            m.set_active(checked);
+        }
+        Ok(())
+    }
+
+    pub fn remove_menu_entry(&self, item_idx: u32) -> Result<(), Error> {
+        self.check_handlers.borrow_mut().remove(&item_idx);
+        self.submenus.borrow_mut().remove(&item_idx);
+        let m = self
+            .menu_items
+            .borrow_mut()
+            .remove(&item_idx)
+            .ok_or(Error::InvalidMenuItem(item_idx))?;
+        // Destroying the item also takes it out of its menu, and drops the
+        // submenu attached to it, if any.
+        m.destroy();
+        Ok(())
+    }
+
+    fn menu_item(&self, item_idx: u32) -> Result<gtk::MenuItem, Error> {
+        self.menu_items
+            .borrow()
+            .get(&item_idx)
+            .cloned()
+            .ok_or(Error::InvalidMenuItem(item_idx))
+    }
+
+    pub fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.menu_item(item_idx)?.set_sensitive(enabled);
+        Ok(())
+    }
+
+    pub fn set_menu_entry_visible(&self, item_idx: u32, visible: bool) -> Result<(), Error> {
+        let m = self.menu_item(item_idx)?;
+        // Use no_show_all so a later show_all on the menu doesn't bring
+        // hidden entries back.
+        m.set_no_show_all(!visible);
+        m.set_visible(visible);
+        Ok(())
+    }
+
+    pub fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        let m = self.menu_item(item_idx)?;
+        if !m.is::<gtk::SeparatorMenuItem>() {
+            m.set_label(item_name);
+        }
+        Ok(())
+    }
+
+    // Takes either a path or a name from the icon theme.
+    pub fn set_icon_full(&self, icon: &str, desc: &str) {
+        self.stop_animation();
+        self.ai.set_icon_full(icon, desc);
+        self.remove_icon_file();
+    }
+
+    pub fn set_icon_theme_path(&self, path: &str) {
+        self.ai.set_icon_theme_path(path);
+    }
+
+    pub fn set_icon_from_written_file(&self, file: PathBuf) {
+        self.stop_animation();
+        self.ai.set_icon_full(&file.to_string_lossy(), "icon");
+        self.remove_icon_file();
+        *self.icon_file.borrow_mut() = Some(file);
+    }
+
+    pub fn set_animation(&self, files: Vec<PathBuf>, interval: u32) {
+        self.stop_animation();
+        self.ai.set_icon_full(&files[0].to_string_lossy(), "icon");
+        self.remove_icon_file();
+        let source = glib::timeout_add_local(interval, || {
+            GTK_STASH.with(|stash| {
+                if let Some(stash) = stash.borrow().as_ref() {
+                    stash.next_animation_frame();
+                }
+            });
+            glib::Continue(true)
+        });
+        *self.animation.borrow_mut() = Some(Animation {
+            source,
+            files,
+            frame: 0,
+        });
+    }
+
+    fn next_animation_frame(&self) {
+        if let Some(animation) = self.animation.borrow_mut().as_mut() {
+            animation.frame = (animation.frame + 1) % animation.files.len();
+            let file = &animation.files[animation.frame];
+            self.ai.set_icon_full(&file.to_string_lossy(), "icon");
+        }
+    }
+
+    // Leaves the current frame up, its file is removed along with the next
+    // icon.
+    pub fn stop_animation(&self) {
+        if let Some(animation) = self.animation.borrow_mut().take() {
+            glib::source_remove(animation.source);
+            for (i, file) in animation.files.into_iter().enumerate() {
+                if i == animation.frame {
+                    *self.icon_file.borrow_mut() = Some(file);
+                } else {
+                    fs::remove_file(file).ok();
+                }
+            }
+        }
+    }
+
+    pub fn set_label(&self, label: &str, guide: &str) {
+        self.ai.set_label(label, guide);
+    }
+
+    pub fn set_status(&self, status: Status) {
+        self.ai.set_status(match status {
+            Status::Passive => AppIndicatorStatus::Passive,
+            Status::Active => AppIndicatorStatus::Active,
+            Status::NeedsAttention => AppIndicatorStatus::Attention,
+        });
+    }
+
+    pub fn set_attention_icon_from_written_file(&self, file: PathBuf) {
+        self.ai.set_attention_icon_full(&file.to_string_lossy(), "attention");
+        if let Some(old) = self.attention_file.replace(Some(file)) {
+            fs::remove_file(old).ok();
+        }
+    }
+
+    fn remove_icon_file(&self) {
+        if let Some(file) = self.icon_file.borrow_mut().take() {
+            fs::remove_file(file).ok();
+        }
+    }
+
+    // AppIndicator has no tooltips, the title is the closest there is.
+    pub fn set_tooltip(&self, tooltip: &str) {
+        self.ai.set_title(tooltip);
     }
 }
 
 pub struct Window {
     gtk_loop: Option<thread::JoinHandle<()>>,
+    handle: Handle,
+    notifier: Notifier,
 }
 
 impl Window {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<Window, Error> {
+    pub fn new(event_tx: EventSender, config: ApplicationBuilder) -> Result<Window, Error> {
         let (tx, rx) = channel();
+        #[cfg(feature = "dbus")]
+        let watch_tx = event_tx.clone();
+        let app_name = config.title.clone().unwrap_or_else(|| config.id.clone());
+        let notifier = Notifier::new(&app_name, event_tx.clone());
+        let handle = Handle {
+            running: Arc::new(AtomicBool::new(true)),
+            wait: config.wait_for_backend,
+        };
+        let running = handle.running.clone();
         let gtk_loop = thread::spawn(move || {
-            GTK_STASH.with(|stash| match GtkSystrayApp::new(event_tx) {
+            match GtkSystrayApp::new(event_tx, config) {
                 Ok(data) => {
-                    (*stash.borrow_mut()) = Some(data);
-                    tx.send(Ok(()));
+                    GTK_STASH.with(|stash| *stash.borrow_mut() = Some(data));
+                    tx.send(Ok(())).ok();
                 }
                 Err(e) => {
-                    tx.send(Err(e));
+                    tx.send(Err(e)).ok();
                     return;
                 }
-            });
+            }
             gtk::main();
+            running.store(false, Ordering::SeqCst);
+            GTK_STASH.with(|stash| {
+                if let Some(stash) = stash.borrow_mut().take() {
+                    stash.stop_animation();
+                    stash.remove_icon_file();
+                    if let Some(file) = stash.attention_file.borrow_mut().take() {
+                        fs::remove_file(file).ok();
+                    }
+                    stash.send_event(Event::Shutdown);
+                }
+            });
         });
-        match rx.recv().unwrap() {
-            Ok(()) => Ok(Window {
-                gtk_loop: Some(gtk_loop),
-            }),
+        match rx.recv().map_err(|_| Error::BackendGone)? {
+            Ok(()) => {
+                // libappindicator registers with new watchers by itself, we
+                // only report hosts coming and going. Without a session bus
+                // there is nothing to report.
+                #[cfg(feature = "dbus")]
+                {
+                    if let Err(e) = watcher::watch(watch_tx, || {}) {
+                        log::debug!("Not watching for tray hosts: {}", e);
+                    }
+                }
+                Ok(Window {
+                    gtk_loop: Some(gtk_loop),
+                    handle,
+                    notifier,
+                })
+            }
             Err(e) => Err(e),
         }
     }
 
-    pub fn add_menu_entry(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+}
+
+impl Backend for Window {
+    fn add_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
         let n = item_name.to_owned().clone();
-        run_on_gtk_thread(move |stash: &GtkSystrayApp| {
-            stash.add_menu_entry(item_idx, &n);
-        });
-        Ok(())
+        self.handle.run(move |stash: &GtkSystrayApp| {
+            stash.add_menu_entry(item_idx, parent, position, &n)
+        })
     }
 
-    pub fn add_menu_separator(&self, item_idx: u32) -> Result<(), Error> {
-        run_on_gtk_thread(move |stash: &GtkSystrayApp| {
-            stash.add_menu_separator(item_idx);
-        });
-        Ok(())
+    fn add_menu_separator(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+    ) -> Result<(), Error> {
+        self.handle.run(move |stash: &GtkSystrayApp| {
+            stash.add_menu_separator(item_idx, parent, position)
+        })
+    }
+
+    fn add_submenu(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        let n = item_name.to_owned();
+        self.handle.run(move |stash: &GtkSystrayApp| {
+            stash.add_submenu(item_idx, parent, position, &n)
+        })
+    }
+
+    fn add_check_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+        checked: bool,
+        radio: bool,
+    ) -> Result<(), Error> {
+        let n = item_name.to_owned();
+        self.handle.run(move |stash: &GtkSystrayApp| {
+            stash.add_check_menu_entry(item_idx, parent, position, &n, checked, radio)
+        })
+    }
+
+    fn set_menu_entry_checked(&self, item_idx: u32, checked: bool) -> Result<(), Error> {
+        self.handle.run(move |stash: &GtkSystrayApp| {
+            stash.set_menu_entry_checked(item_idx, checked)
+        })
+    }
+
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), Error> {
+        self.handle.run(move |stash: &GtkSystrayApp| {
+            stash.remove_menu_entry(item_idx)
+        })
+    }
+
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.handle().set_menu_entry_enabled(item_idx, enabled)
+    }
+
+    fn set_menu_entry_visible(&self, item_idx: u32, visible: bool) -> Result<(), Error> {
+        self.handle.run(move |stash: &GtkSystrayApp| {
+            stash.set_menu_entry_visible(item_idx, visible)
+        })
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.handle().set_menu_entry_label(item_idx, item_name)
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        self.handle().set_icon_from_file(file)
+    }
+
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.handle().set_icon_from_name(name)
+    }
+
+    fn set_icon_theme_path(&self, path: &str) -> Result<(), Error> {
+        let n = path.to_owned();
+        self.handle.run(move |stash: &GtkSystrayApp| {
+            stash.set_icon_theme_path(&n);
+            Ok(())
+        })
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.handle().set_icon(icon)
+    }
+
+    fn shutdown(&self) -> Result<(), Error> {
+        self.handle().stop_animation()
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.handle().set_tooltip(tooltip)
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<u32, Error> {
+        self.notifier.notify(notification)
+    }
+
+    fn quit(&mut self) {
+        self.handle().quit()
+    }
+
+    fn handle(&self) -> Arc<dyn BackendHandle> {
+        Arc::new(Window::handle(self))
+    }
+}
+
+// How often a caller waiting on the Gtk thread checks that it still runs.
+// Changes queued just before the loop stops are never carried out.
+const WAIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Everything goes through the Gtk thread anyway, so the handle only needs to
+// know whether it still runs, and whether to wait for it.
+#[derive(Clone)]
+pub struct Handle {
+    running: Arc<AtomicBool>,
+    wait: bool,
+}
+
+impl Handle {
+    fn check_running(&self) -> Result<(), Error> {
+        if self.running.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(Error::BackendGone)
+        }
     }
 
-    pub fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
-        let n = file.to_owned().clone();
+    // Queues the change on the Gtk thread. With wait_for_backend, blocks
+    // until it is done and returns its result, otherwise errors are logged.
+    fn run<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: std::ops::Fn(&GtkSystrayApp) -> Result<(), Error> + Send + 'static,
+    {
+        self.check_running()?;
+        if !self.wait {
+            run_on_gtk_thread(move |stash: &GtkSystrayApp| {
+                if let Err(e) = f(stash) {
+                    log::debug!("Error updating the tray: {}", e);
+                }
+            });
+            return Ok(());
+        }
+        let (tx, rx) = channel();
         run_on_gtk_thread(move |stash: &GtkSystrayApp| {
-            stash.set_icon_from_file(&n);
+            tx.send(f(stash)).ok();
         });
-        Ok(())
+        loop {
+            match rx.recv_timeout(WAIT_CHECK_INTERVAL) {
+                Ok(result) => return result,
+                Err(RecvTimeoutError::Timeout) => self.check_running()?,
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::BackendGone),
+            }
+        }
     }
+}
 
-    pub fn set_icon_from_resource(&self, resource: &str) -> Result<(), Error> {
-        panic!("Not implemented on this platform!");
+impl BackendHandle for Handle {
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_menu_entry_enabled(item_idx, enabled)
+        })
     }
 
-    pub fn shutdown(&self) -> Result<(), Error> {
-        Ok(())
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        let n = item_name.to_owned();
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_menu_entry_label(item_idx, &n)
+        })
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        // AppIndicator takes any path without complaint.
+        fs::metadata(file)
+            .map_err(|e| Error::OsError(format!("Error reading icon file: {}", e)))?;
+        let n = file.to_owned();
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_icon_full(&n, "icon");
+            Ok(())
+        })
+    }
+
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        let n = name.to_owned();
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_icon_full(&n, &n);
+            Ok(())
+        })
     }
 
-    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
-        panic!("Not implemented on this platform!");
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        // Nothing would remove the file once the loop is gone.
+        self.check_running()?;
+        let path = write_icon_file(icon.best_for_size(ICON_FILE_SIZE))?;
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_icon_from_written_file(path.clone());
+            Ok(())
+        })
+    }
+
+    fn set_animated_icon(&self, frames: &[Icon], frame_duration: Duration) -> Result<(), Error> {
+        self.check_running()?;
+        let mut files = vec![];
+        for frame in frames {
+            match write_icon_file(frame.best_for_size(ICON_FILE_SIZE)) {
+                Ok(file) => files.push(file),
+                Err(e) => {
+                    for file in files {
+                        fs::remove_file(file).ok();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let interval = frame_duration.as_millis().clamp(1, u32::MAX as u128) as u32;
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_animation(files.clone(), interval);
+            Ok(())
+        })
+    }
+
+    fn stop_animation(&self) -> Result<(), Error> {
+        self.run(|stash: &GtkSystrayApp| {
+            stash.stop_animation();
+            Ok(())
+        })
+    }
+
+    fn set_label(&self, label: &str, guide: &str) -> Result<(), Error> {
+        let (l, g) = (label.to_owned(), guide.to_owned());
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_label(&l, &g);
+            Ok(())
+        })
+    }
+
+    fn set_status(&self, status: Status) -> Result<(), Error> {
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_status(status);
+            Ok(())
+        })
+    }
+
+    fn set_attention_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.check_running()?;
+        let path = write_icon_file(icon.best_for_size(ICON_FILE_SIZE))?;
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_attention_icon_from_written_file(path.clone());
+            Ok(())
+        })
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        let n = tooltip.to_owned();
+        self.run(move |stash: &GtkSystrayApp| {
+            stash.set_tooltip(&n);
+            Ok(())
+        })
     }
 
-    pub fn quit(&self) {
+    fn quit(&self) {
         glib::idle_add(|| {
             gtk::main_quit();
             glib::Continue(false)
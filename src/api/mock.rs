@@ -0,0 +1,476 @@
+// In-memory backend for tests, enabled with the "mock" feature. Nothing is
+// shown anywhere: the backend records what the application asks for, and
+// MockTray lets tests look at it and play the user's part.
+
+use super::{Backend, BackendHandle};
+use crate::{ApplicationBuilder, Error, Event, EventSender, Icon, Notification, Status};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MockItemKind {
+    Item,
+    Separator,
+    Submenu,
+    Check,
+    Radio,
+}
+
+/// Snapshot of a menu entry, with the entries of its submenu if it has one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockMenuItem {
+    pub index: u32,
+    pub kind: MockItemKind,
+    pub label: String,
+    pub enabled: bool,
+    pub visible: bool,
+    pub checked: bool,
+    pub children: Vec<MockMenuItem>,
+}
+
+/// The icon last set on the mock tray.
+#[derive(Clone, Debug)]
+pub enum MockIcon {
+    None,
+    File(String),
+    Name(String),
+    Resource(String),
+    Image(Icon),
+    Animated(Vec<Icon>, Duration),
+}
+
+struct Entry {
+    item: MockMenuItem,
+    parent: Option<u32>,
+    // Indices of the submenu entries, in order.
+    children: Vec<u32>,
+}
+
+struct MockState {
+    entries: HashMap<u32, Entry>,
+    root: Vec<u32>,
+    icon: MockIcon,
+    icon_theme_path: Option<String>,
+    attention_icon: Option<Icon>,
+    tooltip: String,
+    label: (String, String),
+    status: Status,
+    notifications: Vec<Notification>,
+    quit: bool,
+}
+
+impl MockState {
+    fn snapshot(&self, idx: u32) -> MockMenuItem {
+        let entry = &self.entries[&idx];
+        let mut item = entry.item.clone();
+        item.children = entry.children.iter().map(|&c| self.snapshot(c)).collect();
+        item
+    }
+
+    fn entry_mut(&mut self, idx: u32) -> Result<&mut MockMenuItem, Error> {
+        match self.entries.get_mut(&idx) {
+            Some(e) => Ok(&mut e.item),
+            None => Err(Error::InvalidMenuItem(idx)),
+        }
+    }
+
+    fn insert(
+        &mut self,
+        idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        kind: MockItemKind,
+        label: &str,
+        checked: bool,
+    ) -> Result<(), Error> {
+        let siblings = match parent {
+            None => &mut self.root,
+            Some(p) => match self.entries.get_mut(&p) {
+                Some(e) if e.item.kind == MockItemKind::Submenu => &mut e.children,
+                _ => return Err(Error::InvalidMenuItem(p)),
+            },
+        };
+        let position = position
+            .map(|p| p as usize)
+            .unwrap_or(siblings.len())
+            .min(siblings.len());
+        siblings.insert(position, idx);
+        let item = MockMenuItem {
+            index: idx,
+            kind,
+            label: label.to_owned(),
+            enabled: true,
+            visible: true,
+            checked,
+            children: vec![],
+        };
+        self.entries.insert(
+            idx,
+            Entry {
+                item,
+                parent,
+                children: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    fn remove(&mut self, idx: u32) -> Result<(), Error> {
+        let entry = match self.entries.remove(&idx) {
+            Some(e) => e,
+            None => return Err(Error::InvalidMenuItem(idx)),
+        };
+        for child in entry.children {
+            self.remove(child)?;
+        }
+        match entry.parent {
+            None => self.root.retain(|&i| i != idx),
+            Some(p) => {
+                if let Some(parent) = self.entries.get_mut(&p) {
+                    parent.children.retain(|&i| i != idx);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Test side of the mock backend, see `Application::mock`. Clones share the
+/// same tray.
+#[derive(Clone)]
+pub struct MockTray {
+    state: Arc<Mutex<MockState>>,
+    event_tx: EventSender,
+}
+
+impl MockTray {
+    /// The whole menu, top level entries first.
+    pub fn menu(&self) -> Vec<MockMenuItem> {
+        let state = self.state.lock().unwrap();
+        state.root.iter().map(|&i| state.snapshot(i)).collect()
+    }
+
+    pub fn menu_item(&self, index: u32) -> Option<MockMenuItem> {
+        let state = self.state.lock().unwrap();
+        if state.entries.contains_key(&index) {
+            Some(state.snapshot(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn icon(&self) -> MockIcon {
+        self.state.lock().unwrap().icon.clone()
+    }
+
+    pub fn icon_theme_path(&self) -> Option<String> {
+        self.state.lock().unwrap().icon_theme_path.clone()
+    }
+
+    pub fn attention_icon(&self) -> Option<Icon> {
+        self.state.lock().unwrap().attention_icon.clone()
+    }
+
+    pub fn tooltip(&self) -> String {
+        self.state.lock().unwrap().tooltip.clone()
+    }
+
+    /// Label and guide, see `Application::set_label`.
+    pub fn label(&self) -> (String, String) {
+        self.state.lock().unwrap().label.clone()
+    }
+
+    pub fn status(&self) -> Status {
+        self.state.lock().unwrap().status
+    }
+
+    /// Every notification shown so far, oldest first. Their ids count up
+    /// from 1 in this order.
+    pub fn notifications(&self) -> Vec<Notification> {
+        self.state.lock().unwrap().notifications.clone()
+    }
+
+    /// Whether the application quit.
+    pub fn is_quit(&self) -> bool {
+        self.state.lock().unwrap().quit
+    }
+
+    /// Clicks a menu item, like a user would. Fails for entries that can't
+    /// be clicked: missing, disabled, hidden, separators and submenus.
+    pub fn activate(&self, index: u32) -> Result<(), Error> {
+        let clickable = self.menu_item(index).map(|i| {
+            i.enabled
+                && i.visible
+                && i.kind != MockItemKind::Separator
+                && i.kind != MockItemKind::Submenu
+        });
+        if clickable != Some(true) {
+            return Err(Error::InvalidMenuItem(index));
+        }
+        self.event_tx.send(Event::MenuItemActivated { index });
+        Ok(())
+    }
+
+    /// Clicks a check or radio item. The application toggles it when it
+    /// dispatches the click, and reports `Event::CheckToggled`.
+    pub fn toggle(&self, index: u32) -> Result<(), Error> {
+        match self.menu_item(index).map(|i| i.kind) {
+            Some(MockItemKind::Check) | Some(MockItemKind::Radio) => self.activate(index),
+            _ => Err(Error::InvalidMenuItem(index)),
+        }
+    }
+
+    /// Sends any event to the application, for clicks, scrolling and the
+    /// like.
+    pub fn send_event(&self, event: Event) {
+        self.event_tx.send(event);
+    }
+}
+
+pub struct Window {
+    tray: MockTray,
+}
+
+impl Window {
+    pub fn new(event_tx: EventSender, config: ApplicationBuilder) -> Result<Window, Error> {
+        let icon = match (config.icon_name, config.icon) {
+            (Some(name), _) => MockIcon::Name(name),
+            (None, Some(icon)) => MockIcon::Image(icon),
+            (None, None) => MockIcon::None,
+        };
+        let state = MockState {
+            entries: HashMap::new(),
+            root: vec![],
+            icon,
+            icon_theme_path: None,
+            attention_icon: None,
+            tooltip: config.title.unwrap_or_default(),
+            label: (String::new(), String::new()),
+            status: Status::Active,
+            notifications: vec![],
+            quit: false,
+        };
+        Ok(Window {
+            tray: MockTray {
+                state: Arc::new(Mutex::new(state)),
+                event_tx,
+            },
+        })
+    }
+
+    pub fn handle(&self) -> Handle {
+        Handle {
+            state: self.tray.state.clone(),
+            event_tx: self.tray.event_tx.clone(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.tray.state.lock().unwrap()
+    }
+}
+
+impl Backend for Window {
+    fn add_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        self.state()
+            .insert(item_idx, parent, position, MockItemKind::Item, item_name, false)
+    }
+
+    fn add_menu_separator(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+    ) -> Result<(), Error> {
+        self.state()
+            .insert(item_idx, parent, position, MockItemKind::Separator, "", false)
+    }
+
+    fn add_submenu(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        self.state()
+            .insert(item_idx, parent, position, MockItemKind::Submenu, item_name, false)
+    }
+
+    fn add_check_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+        checked: bool,
+        radio: bool,
+    ) -> Result<(), Error> {
+        let kind = if radio {
+            MockItemKind::Radio
+        } else {
+            MockItemKind::Check
+        };
+        self.state()
+            .insert(item_idx, parent, position, kind, item_name, checked)
+    }
+
+    fn set_menu_entry_checked(&self, item_idx: u32, checked: bool) -> Result<(), Error> {
+        self.state().entry_mut(item_idx)?.checked = checked;
+        Ok(())
+    }
+
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), Error> {
+        self.state().remove(item_idx)
+    }
+
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.handle().set_menu_entry_enabled(item_idx, enabled)
+    }
+
+    fn set_menu_entry_visible(&self, item_idx: u32, visible: bool) -> Result<(), Error> {
+        self.state().entry_mut(item_idx)?.visible = visible;
+        Ok(())
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.handle().set_menu_entry_label(item_idx, item_name)
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        self.handle().set_icon_from_file(file)
+    }
+
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.handle().set_icon_from_name(name)
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.handle().set_icon(icon)
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.handle().set_tooltip(tooltip)
+    }
+
+    fn shutdown(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn quit(&mut self) {
+        self.handle().quit()
+    }
+
+    fn handle(&self) -> Arc<dyn BackendHandle> {
+        Arc::new(Window::handle(self))
+    }
+
+    fn set_icon_theme_path(&self, path: &str) -> Result<(), Error> {
+        self.state().icon_theme_path = Some(path.to_owned());
+        Ok(())
+    }
+
+    fn set_icon_from_resource(&self, resource: &str) -> Result<(), Error> {
+        self.state().icon = MockIcon::Resource(resource.to_owned());
+        Ok(())
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<u32, Error> {
+        let mut state = self.state();
+        state.notifications.push(notification.clone());
+        Ok(state.notifications.len() as u32)
+    }
+
+    fn mock(&self) -> Option<MockTray> {
+        Some(self.tray.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    state: Arc<Mutex<MockState>>,
+    event_tx: EventSender,
+}
+
+impl Handle {
+    fn set_icon_state(&self, icon: MockIcon) -> Result<(), Error> {
+        self.state.lock().unwrap().icon = icon;
+        Ok(())
+    }
+}
+
+impl BackendHandle for Handle {
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.state.lock().unwrap().entry_mut(item_idx)?.enabled = enabled;
+        Ok(())
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.state.lock().unwrap().entry_mut(item_idx)?.label = item_name.to_owned();
+        Ok(())
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        self.set_icon_state(MockIcon::File(file.to_owned()))
+    }
+
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.set_icon_state(MockIcon::Name(name.to_owned()))
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.set_icon_state(MockIcon::Image(icon.clone()))
+    }
+
+    fn set_animated_icon(&self, frames: &[Icon], frame_duration: Duration) -> Result<(), Error> {
+        self.set_icon_state(MockIcon::Animated(frames.to_vec(), frame_duration))
+    }
+
+    fn stop_animation(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        // Animations stop on their first frame here, as no time passes.
+        let first = match state.icon {
+            MockIcon::Animated(ref frames, _) => frames[0].clone(),
+            _ => return Ok(()),
+        };
+        state.icon = MockIcon::Image(first);
+        Ok(())
+    }
+
+    fn set_label(&self, label: &str, guide: &str) -> Result<(), Error> {
+        self.state.lock().unwrap().label = (label.to_owned(), guide.to_owned());
+        Ok(())
+    }
+
+    fn set_status(&self, status: Status) -> Result<(), Error> {
+        self.state.lock().unwrap().status = status;
+        Ok(())
+    }
+
+    fn set_attention_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.state.lock().unwrap().attention_icon = Some(icon.clone());
+        Ok(())
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.state.lock().unwrap().tooltip = tooltip.to_owned();
+        Ok(())
+    }
+
+    fn quit(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.quit {
+            state.quit = true;
+            self.event_tx.send(Event::Shutdown);
+        }
+    }
+}
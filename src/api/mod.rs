@@ -1,11 +1,288 @@
+// Platform backends. Every backend compiled in for the target implements
+// Backend for its Window and BackendHandle for its Handle, and is picked at
+// runtime by `create`.
+
 #[cfg(target_os = "windows")]
-#[path = "win32/mod.rs"]
-pub mod api;
+pub mod win32;
+
+#[cfg(all(target_os = "linux", feature = "sni"))]
+pub mod sni;
 
-#[cfg(target_os = "linux")]
-#[path = "linux/mod.rs"]
-pub mod api;
+#[cfg(all(target_os = "linux", feature = "xembed"))]
+pub mod xembed;
+
+#[cfg(all(target_os = "linux", feature = "appindicator"))]
+pub mod linux;
 
 #[cfg(target_os = "macos")]
-#[path = "cocoa/mod.rs"]
-pub mod api;
+pub mod cocoa;
+
+#[cfg(feature = "mock")]
+pub mod mock;
+
+#[cfg(all(
+    target_os = "linux",
+    feature = "dbus",
+    any(feature = "appindicator", feature = "sni", feature = "xembed")
+))]
+mod notifications;
+
+// Without the session bus there is no notification server to talk to.
+#[cfg(all(
+    target_os = "linux",
+    not(feature = "dbus"),
+    any(feature = "appindicator", feature = "xembed")
+))]
+mod notifications {
+    use crate::{Error, EventSender, Notification};
+
+    pub(crate) struct Notifier;
+
+    impl Notifier {
+        pub fn new(_app_name: &str, _event_tx: EventSender) -> Notifier {
+            Notifier
+        }
+
+        pub fn notify(&self, _notification: &Notification) -> Result<u32, Error> {
+            Err(Error::Unsupported {
+                feature: "notifications",
+            })
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+mod watcher;
+
+#[cfg(target_os = "windows")]
+pub(crate) use self::win32::is_tray_available;
+
+#[cfg(all(target_os = "linux", feature = "dbus", not(feature = "xembed")))]
+pub(crate) use self::watcher::is_tray_available;
+
+#[cfg(all(target_os = "linux", feature = "xembed", not(feature = "dbus")))]
+pub(crate) use self::xembed::is_tray_available;
+
+// Either kind of tray will do.
+#[cfg(all(target_os = "linux", feature = "dbus", feature = "xembed"))]
+pub(crate) fn is_tray_available() -> bool {
+    watcher::is_tray_available() || xembed::is_tray_available()
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    all(target_os = "linux", any(feature = "dbus", feature = "xembed"))
+)))]
+pub(crate) fn is_tray_available() -> bool {
+    false
+}
+
+use crate::{ApplicationBuilder, Error, EventSender, Icon, Notification, Status};
+use std::{sync::Arc, time::Duration};
+
+/// Tray implementations that can back an `Application`, see
+/// `Application::with_backend`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BackendKind {
+    /// Shell notification icon on Windows.
+    Win32,
+    /// StatusNotifierItem over D-Bus, with the "sni" feature on Linux.
+    StatusNotifierItem,
+    /// XEmbed system tray on X11, with the "xembed" feature on Linux.
+    XEmbed,
+    /// GTK and libappindicator, with the "appindicator" feature on Linux.
+    AppIndicator,
+    /// In-memory tray for tests, with the "mock" feature, see
+    /// `Application::mock`. Never picked automatically.
+    Mock,
+}
+
+impl BackendKind {
+    /// The backends compiled in for this target, in the order automatic
+    /// selection tries them.
+    pub fn available() -> &'static [BackendKind] {
+        &[
+            #[cfg(target_os = "windows")]
+            BackendKind::Win32,
+            #[cfg(all(target_os = "linux", feature = "sni"))]
+            BackendKind::StatusNotifierItem,
+            #[cfg(all(target_os = "linux", feature = "xembed"))]
+            BackendKind::XEmbed,
+            #[cfg(all(target_os = "linux", feature = "appindicator"))]
+            BackendKind::AppIndicator,
+        ]
+    }
+}
+
+/// Platform side of an `Application`. Menu entries are identified by the
+/// index the application gives them.
+pub trait Backend {
+    fn add_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error>;
+    fn add_menu_separator(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+    ) -> Result<(), Error>;
+    fn add_submenu(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error>;
+    fn add_check_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+        checked: bool,
+        radio: bool,
+    ) -> Result<(), Error>;
+    fn set_menu_entry_checked(&self, item_idx: u32, checked: bool) -> Result<(), Error>;
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), Error>;
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error>;
+    fn set_menu_entry_visible(&self, item_idx: u32, visible: bool) -> Result<(), Error>;
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error>;
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error>;
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error>;
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error>;
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error>;
+    fn shutdown(&self) -> Result<(), Error>;
+    fn quit(&mut self);
+    fn handle(&self) -> Arc<dyn BackendHandle>;
+
+    fn set_icon_theme_path(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::Unsupported {
+            feature: "icon theme paths",
+        })
+    }
+
+    fn set_icon_from_resource(&self, _resource: &str) -> Result<(), Error> {
+        Err(Error::Unsupported {
+            feature: "icon resources",
+        })
+    }
+
+    fn set_icon_from_buffer(&self, _buffer: &[u8], _width: u32, _height: u32) -> Result<(), Error> {
+        Err(Error::Unsupported {
+            feature: "icons from buffers",
+        })
+    }
+
+    // Shows the notification and returns its id.
+    fn notify(&self, _notification: &Notification) -> Result<u32, Error> {
+        Err(Error::Unsupported {
+            feature: "notifications",
+        })
+    }
+
+    #[cfg(feature = "mock")]
+    fn mock(&self) -> Option<mock::MockTray> {
+        None
+    }
+}
+
+/// The part of a `Backend` that can be used from any thread, see
+/// `TrayHandle`.
+pub trait BackendHandle: Send + Sync {
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error>;
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error>;
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error>;
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error>;
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error>;
+    fn set_animated_icon(&self, frames: &[Icon], frame_duration: Duration) -> Result<(), Error>;
+    fn stop_animation(&self) -> Result<(), Error>;
+    fn set_label(&self, label: &str, guide: &str) -> Result<(), Error>;
+    fn set_status(&self, status: Status) -> Result<(), Error>;
+    fn set_attention_icon(&self, icon: &Icon) -> Result<(), Error>;
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error>;
+    fn quit(&self);
+}
+
+// Starts the given backend. Failures keep their cause as the source of
+// Error::BackendInit. Builds without any backend only have the error arm.
+#[cfg_attr(
+    not(any(
+        target_os = "windows",
+        all(
+            target_os = "linux",
+            any(feature = "sni", feature = "xembed", feature = "appindicator")
+        ),
+        feature = "mock"
+    )),
+    allow(dead_code, unused_variables, unreachable_code)
+)]
+fn create_backend(
+    kind: BackendKind,
+    event_tx: EventSender,
+    config: ApplicationBuilder,
+) -> Result<Box<dyn Backend>, Error> {
+    fn boxed<B: Backend + 'static>(backend: B) -> Box<dyn Backend> {
+        Box::new(backend)
+    }
+
+    let backend: Result<Box<dyn Backend>, Error> = match kind {
+        #[cfg(target_os = "windows")]
+        BackendKind::Win32 => win32::Window::new(event_tx, config).map(boxed),
+        #[cfg(all(target_os = "linux", feature = "sni"))]
+        BackendKind::StatusNotifierItem => sni::Window::new(event_tx, config).map(boxed),
+        #[cfg(all(target_os = "linux", feature = "xembed"))]
+        BackendKind::XEmbed => xembed::Window::new(event_tx, config).map(boxed),
+        #[cfg(all(target_os = "linux", feature = "appindicator"))]
+        BackendKind::AppIndicator => linux::Window::new(event_tx, config).map(boxed),
+        #[cfg(feature = "mock")]
+        BackendKind::Mock => mock::Window::new(event_tx, config).map(boxed),
+        #[allow(unreachable_patterns)]
+        _ => {
+            return Err(Error::Unsupported {
+                feature: match kind {
+                    BackendKind::Win32 => "the Win32 backend",
+                    BackendKind::StatusNotifierItem => "the StatusNotifierItem backend",
+                    BackendKind::XEmbed => "the XEmbed backend",
+                    BackendKind::AppIndicator => "the AppIndicator backend",
+                    BackendKind::Mock => "the mock backend",
+                },
+            })
+        }
+    };
+    backend.map_err(Error::into_backend_init)
+}
+
+// Starts the requested backend, or the first available one that starts if
+// none is given.
+pub(crate) fn create(
+    kind: Option<BackendKind>,
+    event_tx: EventSender,
+    config: ApplicationBuilder,
+) -> Result<(Box<dyn Backend>, BackendKind), Error> {
+    if let Some(kind) = kind {
+        return create_backend(kind, event_tx, config).map(|b| (b, kind));
+    }
+    let mut failures = vec![];
+    for &kind in BackendKind::available() {
+        match create_backend(kind, event_tx.clone(), config.clone()) {
+            Ok(backend) => {
+                log::debug!("Using the {:?} tray backend", kind);
+                return Ok((backend, kind));
+            }
+            Err(e) => {
+                log::debug!("The {:?} tray backend failed to start: {}", kind, e);
+                failures.push((kind, e));
+            }
+        }
+    }
+    if failures.is_empty() {
+        return Err(Error::Unsupported {
+            feature: "tray icons on this platform",
+        });
+    }
+    Err(Error::NoBackend { failures })
+}
@@ -0,0 +1,172 @@
+// Desktop notifications on Linux, through the org.freedesktop.Notifications
+// server on the session bus. The server signals clicks and closed
+// notifications to every client, so only those about our ids are passed on.
+
+use super::watcher::dbus_error;
+use crate::{CloseReason, Error, Event, EventSender, Notification, Urgency};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    thread,
+};
+use zbus::{
+    blocking::{fdo::DBusProxy, Connection, MessageIterator},
+    message::Type,
+    zvariant::{StructureBuilder, Value},
+    MatchRule,
+};
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+
+// Action key the server uses for clicks on the notification itself.
+const DEFAULT_ACTION: &str = "default";
+
+// Size of the image sent along, servers scale it down to what they show.
+const IMAGE_SIZE: u32 = 64;
+
+pub(crate) struct Notifier {
+    app_name: String,
+    event_tx: EventSender,
+    // Connected on first use, most applications never notify.
+    conn: Mutex<Option<Connection>>,
+    // Our notifications that are still up.
+    ids: Arc<Mutex<HashSet<u32>>>,
+}
+
+impl Notifier {
+    pub fn new(app_name: &str, event_tx: EventSender) -> Notifier {
+        Notifier {
+            app_name: app_name.to_owned(),
+            event_tx,
+            conn: Mutex::new(None),
+            ids: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    fn connection(&self) -> Result<Connection, Error> {
+        let mut conn = self.conn.lock().unwrap();
+        if let Some(ref conn) = *conn {
+            return Ok(conn.clone());
+        }
+        let new_conn = Connection::session().map_err(dbus_error)?;
+        let signals = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .interface(NOTIFICATIONS_NAME)
+            .and_then(|b| b.path(NOTIFICATIONS_PATH))
+            .map_err(dbus_error)?
+            .build();
+        DBusProxy::new(&new_conn)
+            .map_err(dbus_error)?
+            .add_match_rule(signals)
+            .map_err(|e| dbus_error(e.into()))?;
+        // Created before anything is sent, so no signal is missed.
+        let messages = MessageIterator::from(&new_conn);
+        let ids = self.ids.clone();
+        let event_tx = self.event_tx.clone();
+        thread::spawn(move || listen(messages, ids, event_tx));
+        *conn = Some(new_conn.clone());
+        Ok(new_conn)
+    }
+
+    pub fn notify(&self, notification: &Notification) -> Result<u32, Error> {
+        let conn = self.connection()?;
+        let mut actions = vec![DEFAULT_ACTION.to_owned(), String::new()];
+        for (i, label) in notification.actions.iter().enumerate() {
+            actions.push(i.to_string());
+            actions.push(label.clone());
+        }
+        let urgency: u8 = match notification.urgency {
+            Urgency::Low => 0,
+            Urgency::Normal => 1,
+            Urgency::Critical => 2,
+        };
+        let mut hints = HashMap::new();
+        hints.insert("urgency", Value::from(urgency));
+        if let Some(ref icon) = notification.icon {
+            let image = icon.best_for_size(IMAGE_SIZE);
+            let image_data = StructureBuilder::new()
+                .add_field(image.width as i32)
+                .add_field(image.height as i32)
+                .add_field(image.width as i32 * 4)
+                .add_field(true)
+                .add_field(8i32)
+                .add_field(4i32)
+                .add_field(image.rgba.clone())
+                .build()
+                .map_err(|e| dbus_error(e.into()))?;
+            hints.insert("image-data", Value::from(image_data));
+        }
+        let timeout = notification
+            .timeout
+            .map(|t| t.as_millis().min(i32::MAX as u128) as i32)
+            .unwrap_or(-1);
+        // Held until our id is in, so the listener can't drop signals about it.
+        let mut ids = self.ids.lock().unwrap();
+        let reply = conn
+            .call_method(
+                Some(NOTIFICATIONS_NAME),
+                NOTIFICATIONS_PATH,
+                Some(NOTIFICATIONS_NAME),
+                "Notify",
+                &(
+                    self.app_name.as_str(),
+                    0u32,
+                    "",
+                    notification.title.as_str(),
+                    notification.body.as_str(),
+                    actions,
+                    hints,
+                    timeout,
+                ),
+            )
+            .map_err(dbus_error)?;
+        let id = reply.body().deserialize::<u32>().map_err(dbus_error)?;
+        ids.insert(id);
+        Ok(id)
+    }
+}
+
+// Passes on the signals about our notifications until the application is
+// gone.
+fn listen(messages: MessageIterator, ids: Arc<Mutex<HashSet<u32>>>, event_tx: EventSender) {
+    for msg in messages {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(_) => continue,
+        };
+        let header = msg.header();
+        if header.message_type() != Type::Signal
+            || header.interface().map(|i| i.as_str()) != Some(NOTIFICATIONS_NAME)
+        {
+            continue;
+        }
+        let event = match header.member().map(|m| m.as_str()) {
+            Some("ActionInvoked") => match msg.body().deserialize::<(u32, String)>() {
+                Ok((id, action)) if ids.lock().unwrap().contains(&id) => {
+                    Event::NotificationActivated {
+                        id,
+                        action: action.parse().ok(),
+                    }
+                }
+                _ => continue,
+            },
+            Some("NotificationClosed") => match msg.body().deserialize::<(u32, u32)>() {
+                Ok((id, reason)) if ids.lock().unwrap().remove(&id) => Event::NotificationClosed {
+                    id,
+                    reason: match reason {
+                        1 => CloseReason::Expired,
+                        2 => CloseReason::Dismissed,
+                        3 => CloseReason::Closed,
+                        _ => CloseReason::Unknown,
+                    },
+                },
+                _ => continue,
+            },
+            _ => continue,
+        };
+        if !event_tx.send(event) {
+            break;
+        }
+    }
+}
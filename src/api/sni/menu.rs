@@ -0,0 +1,484 @@
+// com.canonical.dbusmenu, the menu the tray host shows for the item.
+//
+// Hosts ask for the layout and properties when they need them, and refetch
+// them when we send LayoutUpdated or ItemsPropertiesUpdated. Our menu index
+// `idx` is exported as dbusmenu id `idx + 1`, as id 0 is the root.
+
+use super::Tray;
+use crate::{Event, EventSender};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use zbus::{
+    fdo, interface,
+    zvariant::{OwnedValue, StructureBuilder, Value},
+};
+
+pub(crate) const MENU_PATH: &str = "/MenuBar";
+pub(crate) const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+
+pub(crate) type Properties = HashMap<String, OwnedValue>;
+type Layout = (i32, Properties, Vec<OwnedValue>);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    Standard,
+    Separator,
+    Submenu,
+    Check,
+    Radio,
+}
+
+pub(crate) struct Entry {
+    pub kind: EntryKind,
+    pub label: String,
+    pub enabled: bool,
+    pub visible: bool,
+    pub checked: bool,
+    parent: Option<u32>,
+    children: Vec<u32>,
+}
+
+impl Entry {
+    pub fn new(kind: EntryKind, label: &str) -> Entry {
+        Entry {
+            kind,
+            label: label.to_owned(),
+            enabled: true,
+            visible: true,
+            checked: false,
+            parent: None,
+            children: vec![],
+        }
+    }
+
+    fn properties(&self) -> Properties {
+        let mut props = Properties::new();
+        let mut add = |name: &str, value: Value| {
+            props.insert(name.to_owned(), value.try_to_owned().unwrap());
+        };
+        if self.kind == EntryKind::Separator {
+            add("type", Value::from("separator"));
+        } else {
+            // Underscores mark mnemonics, double them to show them as is.
+            add("label", Value::from(self.label.replace('_', "__")));
+        }
+        add("enabled", Value::from(self.enabled));
+        add("visible", Value::from(self.visible));
+        match self.kind {
+            EntryKind::Submenu => add("children-display", Value::from("submenu")),
+            EntryKind::Check | EntryKind::Radio => {
+                let toggle = if self.kind == EntryKind::Check {
+                    "checkmark"
+                } else {
+                    "radio"
+                };
+                add("toggle-type", Value::from(toggle));
+                add("toggle-state", Value::from(self.checked as i32));
+            }
+            EntryKind::Standard | EntryKind::Separator => {}
+        }
+        props
+    }
+}
+
+pub(crate) fn dbus_id(idx: u32) -> i32 {
+    idx as i32 + 1
+}
+
+fn menu_idx(id: i32) -> Option<u32> {
+    if id > 0 {
+        Some(id as u32 - 1)
+    } else {
+        None
+    }
+}
+
+// Menu entries by index, and the order of entries in each menu.
+#[derive(Default)]
+pub(crate) struct MenuTree {
+    entries: HashMap<u32, Entry>,
+    root: Vec<u32>,
+    pub revision: u32,
+}
+
+impl MenuTree {
+    pub fn get_mut(&mut self, idx: u32) -> Option<&mut Entry> {
+        self.entries.get_mut(&idx)
+    }
+
+    // Adds the entry at `position` in its parent, or at the end. Returns
+    // false if the parent is not a known submenu.
+    pub fn insert(
+        &mut self,
+        idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        mut entry: Entry,
+    ) -> bool {
+        self.remove(idx);
+        let siblings = match parent {
+            None => &mut self.root,
+            Some(p) => match self.entries.get_mut(&p) {
+                Some(e) if e.kind == EntryKind::Submenu => &mut e.children,
+                _ => return false,
+            },
+        };
+        let position = position
+            .map(|p| p as usize)
+            .unwrap_or(siblings.len())
+            .min(siblings.len());
+        siblings.insert(position, idx);
+        entry.parent = parent;
+        self.entries.insert(idx, entry);
+        true
+    }
+
+    // Removes the entry and everything below it. Returns the dbusmenu id of
+    // its parent if the entry existed.
+    pub fn remove(&mut self, idx: u32) -> Option<i32> {
+        let entry = self.entries.remove(&idx)?;
+        for child in entry.children {
+            self.remove(child);
+        }
+        match entry.parent {
+            None => self.root.retain(|&i| i != idx),
+            Some(p) => {
+                if let Some(parent) = self.entries.get_mut(&p) {
+                    parent.children.retain(|&i| i != idx);
+                }
+            }
+        }
+        Some(entry.parent.map(dbus_id).unwrap_or(0))
+    }
+
+    pub fn properties(&self, idx: u32) -> Option<Properties> {
+        self.entries.get(&idx).map(|e| e.properties())
+    }
+
+    fn children(&self, id: i32) -> Option<&[u32]> {
+        match menu_idx(id) {
+            None if id == 0 => Some(&self.root),
+            None => None,
+            Some(idx) => self.entries.get(&idx).map(|e| &e.children[..]),
+        }
+    }
+
+    fn filtered_properties(&self, id: i32, names: &[String]) -> Option<Properties> {
+        let mut props = match menu_idx(id) {
+            None if id == 0 => {
+                let mut props = Properties::new();
+                props.insert(
+                    "children-display".to_owned(),
+                    Value::from("submenu").try_to_owned().unwrap(),
+                );
+                props
+            }
+            None => return None,
+            Some(idx) => self.properties(idx)?,
+        };
+        if !names.is_empty() {
+            props.retain(|name, _| names.contains(name));
+        }
+        Some(props)
+    }
+
+    // The layout below `id`, `depth` levels deep or all of it if negative.
+    fn layout(&self, id: i32, depth: i32, names: &[String]) -> Option<Layout> {
+        let props = self.filtered_properties(id, names)?;
+        let mut children = vec![];
+        if depth != 0 {
+            for &child in self.children(id)? {
+                let (id, props, grandchildren) = self.layout(dbus_id(child), depth - 1, names)?;
+                let value = StructureBuilder::new()
+                    .add_field(id)
+                    .add_field(props)
+                    .add_field(grandchildren)
+                    .build()
+                    .map(Value::from)
+                    .and_then(|v| v.try_to_owned());
+                if let Ok(value) = value {
+                    children.push(value);
+                }
+            }
+        }
+        Some((id, props, children))
+    }
+}
+
+pub(crate) struct DbusMenu {
+    pub tray: Arc<Mutex<Tray>>,
+    pub event_tx: EventSender,
+}
+
+impl DbusMenu {
+    fn handle_event(&self, id: i32, event_id: &str) -> bool {
+        let idx = match menu_idx(id) {
+            Some(idx) => idx,
+            None if id == 0 => {
+                match event_id {
+                    "opened" => {
+                        self.event_tx.send(Event::MenuOpened);
+                    }
+                    "closed" => {
+                        self.event_tx.send(Event::MenuClosed);
+                    }
+                    _ => {}
+                }
+                return true;
+            }
+            None => return false,
+        };
+        let tray = self.tray.lock().unwrap();
+        let entry = match tray.menu.entries.get(&idx) {
+            Some(e) => e,
+            None => return false,
+        };
+        if event_id == "clicked"
+            && entry.enabled
+            && entry.kind != EntryKind::Separator
+            && entry.kind != EntryKind::Submenu
+        {
+            self.event_tx.send(Event::MenuItemActivated { index: idx });
+        }
+        true
+    }
+}
+
+#[interface(name = "com.canonical.dbusmenu")]
+impl DbusMenu {
+    #[zbus(out_args("revision", "layout"))]
+    fn get_layout(
+        &self,
+        parent_id: i32,
+        recursion_depth: i32,
+        property_names: Vec<String>,
+    ) -> fdo::Result<(u32, Layout)> {
+        let tray = self.tray.lock().unwrap();
+        match tray.menu.layout(parent_id, recursion_depth, &property_names) {
+            Some(layout) => Ok((tray.menu.revision, layout)),
+            None => Err(fdo::Error::InvalidArgs(format!("No menu item {}", parent_id))),
+        }
+    }
+
+    fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        property_names: Vec<String>,
+    ) -> Vec<(i32, Properties)> {
+        let tray = self.tray.lock().unwrap();
+        ids.into_iter()
+            .filter_map(|id| {
+                tray.menu
+                    .filtered_properties(id, &property_names)
+                    .map(|props| (id, props))
+            })
+            .collect()
+    }
+
+    fn get_property(&self, id: i32, name: String) -> fdo::Result<OwnedValue> {
+        let tray = self.tray.lock().unwrap();
+        tray.menu
+            .filtered_properties(id, &[])
+            .and_then(|mut props| props.remove(&name))
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("No property {} on {}", name, id)))
+    }
+
+    fn event(&self, id: i32, event_id: String, _data: Value<'_>, _timestamp: u32) {
+        self.handle_event(id, &event_id);
+    }
+
+    #[zbus(out_args("id_errors"))]
+    fn event_group(&self, events: Vec<(i32, String, OwnedValue, u32)>) -> Vec<i32> {
+        events
+            .into_iter()
+            .filter(|(id, event_id, _, _)| !self.handle_event(*id, event_id))
+            .map(|(id, _, _, _)| id)
+            .collect()
+    }
+
+    #[zbus(out_args("need_update"))]
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    #[zbus(out_args("updates_needed", "id_errors"))]
+    fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (vec![], vec![])
+    }
+
+    #[zbus(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[zbus(property)]
+    fn text_direction(&self) -> &str {
+        "ltr"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        "normal"
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        let tray = self.tray.lock().unwrap();
+        if tray.theme_path.is_empty() {
+            vec![]
+        } else {
+            vec![tray.theme_path.clone()]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn tree(entries: &[(u32, Option<u32>, EntryKind, &str)]) -> MenuTree {
+        let mut tree = MenuTree::default();
+        for &(idx, parent, kind, label) in entries {
+            assert!(tree.insert(idx, parent, None, Entry::new(kind, label)));
+        }
+        tree
+    }
+
+    // The dbusmenu ids below a layout, as "id(children)" lists.
+    fn shape(children: &[Value<'_>]) -> String {
+        let ids: Vec<String> = children
+            .iter()
+            .map(|child| {
+                let child = match child {
+                    Value::Value(v) => &**v,
+                    v => v,
+                };
+                let fields = match child {
+                    Value::Structure(s) => s.fields(),
+                    v => panic!("unexpected layout entry {:?}", v),
+                };
+                let grandchildren = match (&fields[0], &fields[2]) {
+                    (Value::I32(id), Value::Array(a)) if a.is_empty() => return id.to_string(),
+                    (Value::I32(id), Value::Array(a)) => format!("{}({})", id, shape(a.inner())),
+                    v => panic!("unexpected layout entry {:?}", v),
+                };
+                grandchildren
+            })
+            .collect();
+        ids.join(",")
+    }
+
+    fn layout_shape(tree: &MenuTree, id: i32, depth: i32) -> String {
+        let (_, _, children) = tree.layout(id, depth, &[]).unwrap();
+        let children: Vec<Value<'_>> = children.iter().map(|c| Value::from(c.clone())).collect();
+        shape(&children)
+    }
+
+    fn string<'a>(props: &'a Properties, name: &str) -> &'a str {
+        <&str>::try_from(&props[name]).unwrap()
+    }
+
+    #[test]
+    fn inserts_at_position() {
+        let mut tree = tree(&[
+            (0, None, EntryKind::Standard, "a"),
+            (1, None, EntryKind::Submenu, "sub"),
+            (2, Some(1), EntryKind::Standard, "b"),
+        ]);
+        assert!(tree.insert(3, None, Some(0), Entry::new(EntryKind::Standard, "c")));
+        assert!(tree.insert(4, Some(1), Some(0), Entry::new(EntryKind::Check, "d")));
+        // Positions past the end append.
+        assert!(tree.insert(5, None, Some(100), Entry::new(EntryKind::Separator, "")));
+        assert_eq!(layout_shape(&tree, 0, -1), "4,1,2(5,3),6");
+    }
+
+    #[test]
+    fn rejects_unknown_parent() {
+        let mut tree = tree(&[(0, None, EntryKind::Standard, "a")]);
+        assert!(!tree.insert(1, Some(0), None, Entry::new(EntryKind::Standard, "b")));
+        assert!(!tree.insert(1, Some(7), None, Entry::new(EntryKind::Standard, "b")));
+        assert_eq!(layout_shape(&tree, 0, -1), "1");
+    }
+
+    #[test]
+    fn reinserting_moves_entry() {
+        let mut tree = tree(&[
+            (0, None, EntryKind::Standard, "a"),
+            (1, None, EntryKind::Standard, "b"),
+        ]);
+        assert!(tree.insert(1, None, Some(0), Entry::new(EntryKind::Standard, "b")));
+        assert_eq!(layout_shape(&tree, 0, -1), "2,1");
+    }
+
+    #[test]
+    fn removes_entry_with_children() {
+        let mut tree = tree(&[
+            (0, None, EntryKind::Standard, "a"),
+            (1, None, EntryKind::Submenu, "sub"),
+            (2, Some(1), EntryKind::Submenu, "subsub"),
+            (3, Some(2), EntryKind::Standard, "b"),
+            (4, Some(1), EntryKind::Standard, "c"),
+            (5, None, EntryKind::Standard, "d"),
+        ]);
+        assert_eq!(tree.remove(4), Some(dbus_id(1)));
+        assert_eq!(layout_shape(&tree, 0, -1), "1,2(3(4)),6");
+        assert_eq!(tree.remove(1), Some(0));
+        assert_eq!(layout_shape(&tree, 0, -1), "1,6");
+        assert!(tree.get_mut(2).is_none());
+        assert!(tree.get_mut(3).is_none());
+        assert_eq!(tree.remove(3), None);
+    }
+
+    #[test]
+    fn limits_layout_depth() {
+        let tree = tree(&[
+            (0, None, EntryKind::Submenu, "sub"),
+            (1, Some(0), EntryKind::Submenu, "subsub"),
+            (2, Some(1), EntryKind::Standard, "a"),
+        ]);
+        assert_eq!(layout_shape(&tree, 0, 0), "");
+        assert_eq!(layout_shape(&tree, 0, 1), "1");
+        assert_eq!(layout_shape(&tree, 0, 2), "1(2)");
+        assert_eq!(layout_shape(&tree, 0, -1), "1(2(3))");
+        assert_eq!(layout_shape(&tree, dbus_id(1), -1), "3");
+        assert!(tree.layout(dbus_id(7), -1, &[]).is_none());
+        assert!(tree.layout(-1, -1, &[]).is_none());
+    }
+
+    #[test]
+    fn describes_entries() {
+        let mut tree = tree(&[
+            (0, None, EntryKind::Standard, "Save_as"),
+            (1, None, EntryKind::Separator, ""),
+            (2, None, EntryKind::Radio, "r"),
+            (3, None, EntryKind::Submenu, "sub"),
+        ]);
+        tree.get_mut(2).unwrap().checked = true;
+        tree.get_mut(0).unwrap().enabled = false;
+
+        let item = tree.properties(0).unwrap();
+        assert_eq!(string(&item, "label"), "Save__as");
+        assert!(!bool::try_from(&item["enabled"]).unwrap());
+        assert!(!item.contains_key("toggle-type"));
+
+        let separator = tree.properties(1).unwrap();
+        assert_eq!(string(&separator, "type"), "separator");
+        assert!(!separator.contains_key("label"));
+
+        let radio = tree.properties(2).unwrap();
+        assert_eq!(string(&radio, "toggle-type"), "radio");
+        assert_eq!(i32::try_from(&radio["toggle-state"]).unwrap(), 1);
+
+        let submenu = tree.properties(3).unwrap();
+        assert_eq!(string(&submenu, "children-display"), "submenu");
+
+        let names = vec!["label".to_owned(), "unknown".to_owned()];
+        let filtered = tree.filtered_properties(dbus_id(0), &names).unwrap();
+        assert_eq!(filtered.keys().collect::<Vec<_>>(), vec!["label"]);
+        let root = tree.filtered_properties(0, &[]).unwrap();
+        assert!(root.contains_key("children-display"));
+    }
+}
@@ -0,0 +1,664 @@
+// StatusNotifierItem backend, enabled with the "sni" feature. Exports the item
+// and its menu on the session bus directly, so no GTK is needed.
+//
+// Everything the host can ask for lives in Tray, behind a mutex shared with
+// the D-Bus interfaces. Changes are made from the calling thread, followed by
+// the signal telling the host to refetch. A small loop thread drives icon
+// animations and takes the item off the bus on quit, or once the window is
+// dropped: handles keep the loop channel open, so it never disconnects.
+
+mod menu;
+
+use self::menu::{dbus_id, DbusMenu, Entry, EntryKind, MenuTree, MENU_INTERFACE, MENU_PATH};
+use super::{
+    notifications::Notifier,
+    watcher::{self, dbus_error, WATCHER_NAME, WATCHER_PATH},
+    Backend, BackendHandle,
+};
+use crate::{
+    icon::IconImage, ApplicationBuilder, Category, Error, Event, EventSender, Icon, Notification,
+    ScrollOrientation, Status,
+};
+use serde::Serialize;
+use std::{
+    fs, process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use zbus::{
+    blocking::{connection, Connection},
+    interface,
+    zvariant::{DynamicType, ObjectPath},
+};
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+
+// Width, height and ARGB32 data in network byte order.
+type Pixmap = (i32, i32, Vec<u8>);
+
+pub(crate) struct Tray {
+    id: String,
+    title: String,
+    category: &'static str,
+    status: Status,
+    icon_name: String,
+    icon_pixmap: Vec<Pixmap>,
+    theme_path: String,
+    attention_pixmap: Vec<Pixmap>,
+    tooltip: String,
+    label: String,
+    label_guide: String,
+    animation: Option<Animation>,
+    menu: MenuTree,
+    // Set once the item is off the bus.
+    gone: bool,
+}
+
+struct Animation {
+    frames: Vec<Vec<Pixmap>>,
+    frame: usize,
+    interval: Duration,
+}
+
+enum LoopMessage {
+    // The animation changed, look at it again.
+    Wake,
+    Quit,
+}
+
+fn pixmap(image: &IconImage) -> Pixmap {
+    let mut argb = Vec::with_capacity(image.rgba.len());
+    for p in image.rgba.chunks(4) {
+        argb.extend_from_slice(&[p[3], p[0], p[1], p[2]]);
+    }
+    (image.width as i32, image.height as i32, argb)
+}
+
+// Hosts pick the size they need, so send all of them.
+fn pixmaps(icon: &Icon) -> Vec<Pixmap> {
+    icon.images.iter().map(pixmap).collect()
+}
+
+fn status_name(status: Status) -> &'static str {
+    match status {
+        Status::Passive => "Passive",
+        Status::Active => "Active",
+        Status::NeedsAttention => "NeedsAttention",
+    }
+}
+
+struct StatusNotifierItem {
+    tray: Arc<Mutex<Tray>>,
+    event_tx: EventSender,
+}
+
+#[interface(name = "org.kde.StatusNotifierItem")]
+impl StatusNotifierItem {
+    // The menu is shown by the host from the Menu property.
+    fn context_menu(&self, _x: i32, _y: i32) {}
+
+    fn activate(&self, _x: i32, _y: i32) {
+        self.event_tx.send(Event::LeftClick);
+    }
+
+    fn secondary_activate(&self, _x: i32, _y: i32) {
+        self.event_tx.send(Event::MiddleClick);
+    }
+
+    fn scroll(&self, delta: i32, orientation: String) {
+        // Hosts report scrolling up or left as positive.
+        let orientation = match &orientation[..] {
+            "horizontal" => ScrollOrientation::Horizontal,
+            _ => ScrollOrientation::Vertical,
+        };
+        self.event_tx.send(Event::Scroll {
+            delta: -delta,
+            orientation,
+        });
+    }
+
+    #[zbus(property)]
+    fn category(&self) -> &str {
+        self.tray.lock().unwrap().category
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> String {
+        self.tray.lock().unwrap().id.clone()
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> String {
+        self.tray.lock().unwrap().title.clone()
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        status_name(self.tray.lock().unwrap().status)
+    }
+
+    #[zbus(property)]
+    fn window_id(&self) -> i32 {
+        0
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> String {
+        self.tray.lock().unwrap().theme_path.clone()
+    }
+
+    #[zbus(property)]
+    fn icon_name(&self) -> String {
+        self.tray.lock().unwrap().icon_name.clone()
+    }
+
+    #[zbus(property)]
+    fn icon_pixmap(&self) -> Vec<Pixmap> {
+        self.tray.lock().unwrap().icon_pixmap.clone()
+    }
+
+    #[zbus(property)]
+    fn overlay_icon_name(&self) -> &str {
+        ""
+    }
+
+    #[zbus(property)]
+    fn overlay_icon_pixmap(&self) -> Vec<Pixmap> {
+        vec![]
+    }
+
+    #[zbus(property)]
+    fn attention_icon_name(&self) -> &str {
+        ""
+    }
+
+    #[zbus(property)]
+    fn attention_icon_pixmap(&self) -> Vec<Pixmap> {
+        self.tray.lock().unwrap().attention_pixmap.clone()
+    }
+
+    #[zbus(property)]
+    fn attention_movie_name(&self) -> &str {
+        ""
+    }
+
+    // Icon name, icon pixmaps, title and description.
+    #[zbus(property)]
+    fn tool_tip(&self) -> (String, Vec<Pixmap>, String, String) {
+        let tray = self.tray.lock().unwrap();
+        (String::new(), vec![], tray.tooltip.clone(), String::new())
+    }
+
+    // Lets the host report left clicks through Activate, and show the menu
+    // on right clicks.
+    #[zbus(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn menu(&self) -> ObjectPath<'static> {
+        ObjectPath::from_static_str_unchecked(MENU_PATH)
+    }
+
+    // Ayatana extension, shown next to the icon by some hosts.
+    #[zbus(property)]
+    fn x_ayatana_label(&self) -> String {
+        self.tray.lock().unwrap().label.clone()
+    }
+
+    #[zbus(property)]
+    fn x_ayatana_label_guide(&self) -> String {
+        self.tray.lock().unwrap().label_guide.clone()
+    }
+}
+
+// Switches to the next frame of the animation, if there is one.
+fn next_animation_frame(tray: &Mutex<Tray>) -> bool {
+    let mut tray = tray.lock().unwrap();
+    let pixmap = match tray.animation {
+        Some(ref mut a) => {
+            a.frame = (a.frame + 1) % a.frames.len();
+            a.frames[a.frame].clone()
+        }
+        None => return false,
+    };
+    tray.icon_pixmap = pixmap;
+    true
+}
+
+fn run_loop(handle: Handle, rx: Receiver<LoopMessage>, event_tx: EventSender) {
+    loop {
+        let interval = handle.tray.lock().unwrap().animation.as_ref().map(|a| a.interval);
+        let message = match interval {
+            Some(interval) => rx.recv_timeout(interval),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match message {
+            Ok(LoopMessage::Wake) => {}
+            Ok(LoopMessage::Quit) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                if next_animation_frame(&handle.tray) {
+                    handle.emit_item("NewIcon", &()).ok();
+                }
+            }
+        }
+    }
+    // The watcher drops the item once its name is gone.
+    handle.tray.lock().unwrap().gone = true;
+    handle.conn.release_name(handle.name.as_str()).ok();
+    event_tx.send(Event::Shutdown);
+}
+
+pub struct Window {
+    handle: Handle,
+    notifier: Notifier,
+}
+
+impl Window {
+    pub fn new(event_tx: EventSender, config: ApplicationBuilder) -> Result<Window, Error> {
+        static ITEM_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let category = match config.category {
+            Category::ApplicationStatus => "ApplicationStatus",
+            Category::Communications => "Communications",
+            Category::SystemServices => "SystemServices",
+            Category::Hardware => "Hardware",
+        };
+        let id = config.id;
+        let title = config.title.unwrap_or_else(|| id.clone());
+        let notifier = Notifier::new(&title, event_tx.clone());
+        let tray = Arc::new(Mutex::new(Tray {
+            tooltip: title.clone(),
+            id,
+            title,
+            category,
+            status: Status::Active,
+            icon_name: config.icon_name.unwrap_or_default(),
+            icon_pixmap: config.icon.as_ref().map(pixmaps).unwrap_or_default(),
+            theme_path: String::new(),
+            attention_pixmap: vec![],
+            label: String::new(),
+            label_guide: String::new(),
+            animation: None,
+            menu: MenuTree::default(),
+            gone: false,
+        }));
+        let name = format!(
+            "org.kde.StatusNotifierItem-{}-{}",
+            process::id(),
+            ITEM_COUNT.fetch_add(1, Ordering::SeqCst) + 1
+        );
+        let item = StatusNotifierItem {
+            tray: tray.clone(),
+            event_tx: event_tx.clone(),
+        };
+        let dbus_menu = DbusMenu {
+            tray: tray.clone(),
+            event_tx: event_tx.clone(),
+        };
+        let conn = connection::Builder::session()
+            .and_then(|b| b.name(name.clone()))
+            .and_then(|b| b.serve_at(ITEM_PATH, item))
+            .and_then(|b| b.serve_at(MENU_PATH, dbus_menu))
+            .and_then(|b| b.build())
+            .map_err(dbus_error)?;
+        let (loop_tx, loop_rx) = channel();
+        let handle = Handle {
+            tray,
+            conn,
+            name,
+            loop_tx,
+        };
+        handle.register()?;
+
+        // Panels that restart bring up a new watcher, which doesn't know
+        // about us.
+        let watch_handle = handle.clone();
+        watcher::watch(event_tx.clone(), move || {
+            watch_handle.register().ok();
+        })?;
+        let loop_handle = handle.clone();
+        thread::spawn(move || run_loop(loop_handle, loop_rx, event_tx));
+        Ok(Window { handle, notifier })
+    }
+
+    // Adds the entry and tells the host its parent changed.
+    fn add_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        entry: Entry,
+    ) -> Result<(), Error> {
+        if !self.handle.tray.lock().unwrap().menu.insert(item_idx, parent, position, entry) {
+            return Err(Error::InvalidMenuItem(parent.unwrap_or(item_idx)));
+        }
+        self.handle.layout_updated(parent.map(dbus_id).unwrap_or(0))
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+}
+
+impl Backend for Window {
+    fn add_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        let entry = Entry::new(EntryKind::Standard, item_name);
+        self.add_entry(item_idx, parent, position, entry)
+    }
+
+    fn add_menu_separator(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+    ) -> Result<(), Error> {
+        let entry = Entry::new(EntryKind::Separator, "");
+        self.add_entry(item_idx, parent, position, entry)
+    }
+
+    fn add_submenu(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        let entry = Entry::new(EntryKind::Submenu, item_name);
+        self.add_entry(item_idx, parent, position, entry)
+    }
+
+    fn add_check_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+        checked: bool,
+        radio: bool,
+    ) -> Result<(), Error> {
+        let kind = if radio {
+            EntryKind::Radio
+        } else {
+            EntryKind::Check
+        };
+        let mut entry = Entry::new(kind, item_name);
+        entry.checked = checked;
+        self.add_entry(item_idx, parent, position, entry)
+    }
+
+    fn set_menu_entry_checked(&self, item_idx: u32, checked: bool) -> Result<(), Error> {
+        self.handle.update_entry(item_idx, |e| e.checked = checked)
+    }
+
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), Error> {
+        let parent = self.handle.tray.lock().unwrap().menu.remove(item_idx);
+        match parent {
+            Some(parent) => self.handle.layout_updated(parent),
+            None => Ok(()),
+        }
+    }
+
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.handle.set_menu_entry_enabled(item_idx, enabled)
+    }
+
+    fn set_menu_entry_visible(&self, item_idx: u32, visible: bool) -> Result<(), Error> {
+        self.handle.update_entry(item_idx, |e| e.visible = visible)
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.handle.set_menu_entry_label(item_idx, item_name)
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        self.handle.set_icon_from_file(file)
+    }
+
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.handle.set_icon_from_name(name)
+    }
+
+    fn set_icon_theme_path(&self, path: &str) -> Result<(), Error> {
+        self.handle.tray.lock().unwrap().theme_path = path.to_owned();
+        self.handle.emit_item("NewIconThemePath", &path)
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.handle.set_icon(icon)
+    }
+
+    fn shutdown(&self) -> Result<(), Error> {
+        self.handle.stop_animation()
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.handle.set_tooltip(tooltip)
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<u32, Error> {
+        self.notifier.notify(notification)
+    }
+
+    fn quit(&mut self) {
+        self.handle.quit()
+    }
+
+    fn handle(&self) -> Arc<dyn BackendHandle> {
+        Arc::new(Window::handle(self))
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        self.handle.quit();
+    }
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    tray: Arc<Mutex<Tray>>,
+    conn: Connection,
+    // Bus name the item is exported under.
+    name: String,
+    loop_tx: Sender<LoopMessage>,
+}
+
+impl Handle {
+    fn register(&self) -> Result<(), Error> {
+        if self.tray.lock().unwrap().gone {
+            return Err(Error::BackendGone);
+        }
+        self.conn
+            .call_method(
+                Some(WATCHER_NAME),
+                WATCHER_PATH,
+                Some(WATCHER_NAME),
+                "RegisterStatusNotifierItem",
+                &self.name,
+            )
+            .map(|_| ())
+            .map_err(dbus_error)
+    }
+
+    fn emit_item<B: Serialize + DynamicType>(&self, signal: &str, body: &B) -> Result<(), Error> {
+        if self.tray.lock().unwrap().gone {
+            return Err(Error::BackendGone);
+        }
+        self.conn
+            .emit_signal(None::<&str>, ITEM_PATH, ITEM_INTERFACE, signal, body)
+            .map_err(dbus_error)
+    }
+
+    fn layout_updated(&self, parent: i32) -> Result<(), Error> {
+        let revision = {
+            let mut tray = self.tray.lock().unwrap();
+            if tray.gone {
+                return Err(Error::BackendGone);
+            }
+            tray.menu.revision += 1;
+            tray.menu.revision
+        };
+        self.conn
+            .emit_signal(
+                None::<&str>,
+                MENU_PATH,
+                MENU_INTERFACE,
+                "LayoutUpdated",
+                &(revision, parent),
+            )
+            .map_err(dbus_error)
+    }
+
+    fn update_entry<F: FnOnce(&mut Entry)>(&self, item_idx: u32, f: F) -> Result<(), Error> {
+        let props = {
+            let mut tray = self.tray.lock().unwrap();
+            match tray.menu.get_mut(item_idx) {
+                Some(entry) => f(entry),
+                None => return Err(Error::InvalidMenuItem(item_idx)),
+            }
+            tray.menu.properties(item_idx).unwrap_or_default()
+        };
+        let removed: Vec<(i32, Vec<String>)> = vec![];
+        self.conn
+            .emit_signal(
+                None::<&str>,
+                MENU_PATH,
+                MENU_INTERFACE,
+                "ItemsPropertiesUpdated",
+                &(vec![(dbus_id(item_idx), props)], removed),
+            )
+            .map_err(dbus_error)
+    }
+
+    // Replaces the icon, stopping any animation.
+    fn set_icon_state(&self, name: &str, pixmap: Vec<Pixmap>) -> Result<(), Error> {
+        {
+            let mut tray = self.tray.lock().unwrap();
+            tray.animation = None;
+            tray.icon_name = name.to_owned();
+            tray.icon_pixmap = pixmap;
+        }
+        self.emit_item("NewIcon", &())
+    }
+}
+
+impl BackendHandle for Handle {
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.update_entry(item_idx, |e| e.enabled = enabled)
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.update_entry(item_idx, |e| e.label = item_name.to_owned())
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        let data = fs::read(file)
+            .map_err(|e| Error::OsError(format!("Error reading icon file: {}", e)))?;
+        self.set_icon(&Icon::from_bytes(&data)?)
+    }
+
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.set_icon_state(name, vec![])
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.set_icon_state("", pixmaps(icon))
+    }
+
+    fn set_animated_icon(&self, frames: &[Icon], frame_duration: Duration) -> Result<(), Error> {
+        let frames: Vec<_> = frames.iter().map(pixmaps).collect();
+        {
+            let mut tray = self.tray.lock().unwrap();
+            tray.icon_name = String::new();
+            tray.icon_pixmap = frames[0].clone();
+            tray.animation = Some(Animation {
+                frames,
+                frame: 0,
+                interval: frame_duration.max(Duration::from_millis(1)),
+            });
+        }
+        self.loop_tx
+            .send(LoopMessage::Wake)
+            .map_err(|_| Error::BackendGone)?;
+        self.emit_item("NewIcon", &())
+    }
+
+    fn stop_animation(&self) -> Result<(), Error> {
+        // The current frame stays as the icon.
+        self.tray.lock().unwrap().animation = None;
+        self.loop_tx
+            .send(LoopMessage::Wake)
+            .map_err(|_| Error::BackendGone)
+    }
+
+    fn set_label(&self, label: &str, guide: &str) -> Result<(), Error> {
+        {
+            let mut tray = self.tray.lock().unwrap();
+            tray.label = label.to_owned();
+            tray.label_guide = guide.to_owned();
+        }
+        self.emit_item("XAyatanaNewLabel", &(label, guide))
+    }
+
+    fn set_status(&self, status: Status) -> Result<(), Error> {
+        self.tray.lock().unwrap().status = status;
+        self.emit_item("NewStatus", &status_name(status))
+    }
+
+    fn set_attention_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.tray.lock().unwrap().attention_pixmap = pixmaps(icon);
+        self.emit_item("NewAttentionIcon", &())
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.tray.lock().unwrap().tooltip = tooltip.to_owned();
+        self.emit_item("NewToolTip", &())
+    }
+
+    fn quit(&self) {
+        self.loop_tx.send(LoopMessage::Quit).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_rgba_to_argb() {
+        let rgba = vec![1, 2, 3, 4, 10, 20, 30, 40];
+        let icon = Icon::from_rgba(rgba, 2, 1).unwrap();
+        assert_eq!(pixmaps(&icon), vec![(2, 1, vec![4, 1, 2, 3, 40, 10, 20, 30])]);
+    }
+
+    #[test]
+    fn sends_every_size() {
+        let image = |size: u32| IconImage {
+            width: size,
+            height: size,
+            rgba: vec![size as u8; (size * size * 4) as usize],
+        };
+        let icon = Icon {
+            images: vec![image(16), image(32)],
+        };
+        let sizes: Vec<_> = pixmaps(&icon)
+            .into_iter()
+            .map(|(w, h, argb)| (w, h, argb.len()))
+            .collect();
+        assert_eq!(sizes, vec![(16, 16, 16 * 16 * 4), (32, 32, 32 * 32 * 4)]);
+    }
+}
@@ -0,0 +1,105 @@
+// Tray host detection on Linux. Panels that show StatusNotifierItems register
+// as hosts with the StatusNotifierWatcher on the session bus, and the watcher
+// itself comes and goes with the panel that provides it.
+
+use crate::{Error, Event, EventSender};
+use std::{convert::TryFrom, thread};
+use zbus::{
+    blocking::{fdo::DBusProxy, Connection, MessageIterator},
+    message::Type,
+    zvariant::OwnedValue,
+    MatchRule,
+};
+
+pub(crate) const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+pub(crate) const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+
+pub(crate) fn dbus_error(e: zbus::Error) -> Error {
+    Error::OsError(format!("D-Bus error: {}", e))
+}
+
+fn host_registered(conn: &Connection) -> bool {
+    let reply = conn.call_method(
+        Some(WATCHER_NAME),
+        WATCHER_PATH,
+        Some("org.freedesktop.DBus.Properties"),
+        "Get",
+        &(WATCHER_NAME, "IsStatusNotifierHostRegistered"),
+    );
+    reply
+        .and_then(|m| m.body().deserialize::<OwnedValue>())
+        .ok()
+        .and_then(|v| bool::try_from(v).ok())
+        .unwrap_or(false)
+}
+
+pub(crate) fn is_tray_available() -> bool {
+    match Connection::session() {
+        Ok(conn) => host_registered(&conn),
+        Err(_) => false,
+    }
+}
+
+// Sends TrayHostChanged whenever the answer of is_tray_available changes, and
+// calls `on_watcher` when a new watcher shows up, so items can register with
+// it again. Stops once the application is gone.
+#[cfg_attr(not(any(feature = "appindicator", feature = "sni")), allow(dead_code))]
+pub(crate) fn watch<F>(event_tx: EventSender, on_watcher: F) -> Result<(), Error>
+where
+    F: Fn() + Send + 'static,
+{
+    let conn = Connection::session().map_err(dbus_error)?;
+    let owner_changed = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender("org.freedesktop.DBus")
+        .and_then(|b| b.interface("org.freedesktop.DBus"))
+        .and_then(|b| b.member("NameOwnerChanged"))
+        .and_then(|b| b.add_arg(WATCHER_NAME))
+        .map_err(dbus_error)?
+        .build();
+    let watcher_signals = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface(WATCHER_NAME)
+        .map_err(dbus_error)?
+        .build();
+    let proxy = DBusProxy::new(&conn).map_err(dbus_error)?;
+    for rule in [owner_changed, watcher_signals] {
+        proxy
+            .add_match_rule(rule)
+            .map_err(|e| dbus_error(e.into()))?;
+    }
+    let mut available = host_registered(&conn);
+    thread::spawn(move || {
+        for msg in MessageIterator::from(&conn) {
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(_) => continue,
+            };
+            let header = msg.header();
+            if header.message_type() != Type::Signal {
+                continue;
+            }
+            match header.member().map(|m| m.as_str()) {
+                Some("NameOwnerChanged") => {
+                    let new_owner = msg
+                        .body()
+                        .deserialize::<(String, String, String)>()
+                        .map(|(_, _, new_owner)| new_owner);
+                    if new_owner.map(|o| !o.is_empty()).unwrap_or(false) {
+                        on_watcher();
+                    }
+                }
+                Some("StatusNotifierHostRegistered") | Some("StatusNotifierHostUnregistered") => {}
+                _ => continue,
+            }
+            let now = host_registered(&conn);
+            if now != available {
+                available = now;
+                if !event_tx.send(Event::TrayHostChanged { available }) {
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
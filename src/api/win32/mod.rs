@@ -1,10 +1,20 @@
-use crate::{Error, SystrayEvent};
+use super::{Backend, BackendHandle};
+use crate::{
+    icon::IconImage, ApplicationBuilder, CloseReason, Error, Event, EventSender, Icon,
+    Notification, Status, Urgency,
+};
 use std;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::os::windows::ffi::OsStrExt;
-use std::sync::mpsc::{channel, Sender};
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    mpsc::channel,
+    Arc, Mutex, OnceLock,
+};
 use std::thread;
+use std::time::Duration;
 use winapi::{
     ctypes::{c_ulong, c_ushort},
     shared::{
@@ -17,13 +27,17 @@ use winapi::{
     um::{
         errhandlingapi, libloaderapi,
         shellapi::{
-            self, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAW,
+            self, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_STATE, NIF_TIP, NIIF_ICON_MASK, NIIF_INFO,
+            NIIF_LARGE_ICON, NIIF_NOSOUND, NIIF_USER, NIIF_WARNING, NIM_ADD, NIM_DELETE,
+            NIM_MODIFY, NIN_BALLOONHIDE, NIN_BALLOONTIMEOUT, NIN_BALLOONUSERCLICK, NIS_HIDDEN,
+            NOTIFYICONDATAW,
         },
         winuser::{
             self, CW_USEDEFAULT, IMAGE_ICON, LR_DEFAULTCOLOR, LR_LOADFROMFILE, MENUINFO,
-            MENUITEMINFOW, MFT_SEPARATOR, MFT_STRING, MIIM_FTYPE, MIIM_ID, MIIM_STATE, MIIM_STRING,
-            MIM_APPLYTOSUBMENUS, MIM_STYLE, MNS_NOTIFYBYPOS, WM_DESTROY, WM_USER, WNDCLASSW,
-            WS_OVERLAPPEDWINDOW,
+            MENUITEMINFOW, MFS_CHECKED, MFS_DISABLED, MFT_RADIOCHECK, MFT_SEPARATOR, MFT_STRING,
+            MF_BYPOSITION, MF_CHECKED, MF_ENABLED, MF_GRAYED, MF_UNCHECKED, MIIM_FTYPE, MIIM_ID,
+            MIIM_STATE, MIIM_STRING, MIIM_SUBMENU, MIM_APPLYTOSUBMENUS, MIM_STYLE, MNS_NOTIFYBYPOS,
+            WM_DESTROY, WM_USER, WNDCLASSW, WS_OVERLAPPEDWINDOW,
         },
     },
 };
@@ -44,21 +58,207 @@ struct WindowInfo {
     pub hwnd: HWND,
     pub hinstance: HINSTANCE,
     pub hmenu: HMENU,
+    // Menu items that are hidden, by index. Win32 menus have no hidden
+    // state, so they are taken out of their menu until shown again.
+    pub hidden: Arc<Mutex<HashMap<u32, HiddenItem>>>,
+    // Menus of the submenu items, by index. Those of hidden items are not
+    // attached to the menu tree, so lookups by command don't find their
+    // items.
+    pub submenus: Arc<Mutex<HashMap<u32, Submenu>>>,
 }
 
 unsafe impl Send for WindowInfo {}
 unsafe impl Sync for WindowInfo {}
 
-#[derive(Clone)]
+impl WindowInfo {
+    // Finds the menu an item is shown in, and its position there.
+    fn find_menu_item(&self, item_idx: u32) -> Option<(HMENU, UINT)> {
+        let submenus = self.submenus.lock().unwrap();
+        let menus = std::iter::once(self.hmenu).chain(submenus.values().map(|m| m.0));
+        for hmenu in menus {
+            let count = unsafe { winuser::GetMenuItemCount(hmenu) };
+            for position in 0..count.max(0) as UINT {
+                let mut item = get_menu_item_struct();
+                item.fMask = MIIM_ID;
+                let found =
+                    unsafe { winuser::GetMenuItemInfoW(hmenu, position, TRUE, &mut item) != 0 };
+                if found && item.wID == item_idx {
+                    return Some((hmenu, position));
+                }
+            }
+        }
+        None
+    }
+}
+
+struct HiddenItem {
+    // Menu the item was taken out of, and its position there counting the
+    // other hidden items, which is the position it is given in by the menu
+    // functions.
+    menu: HMENU,
+    position: u32,
+    // Everything needed to insert it again, with the label kept apart.
+    info: MENUITEMINFOW,
+    label: Vec<u16>,
+}
+
+unsafe impl Send for HiddenItem {}
+
+#[derive(Clone, Copy)]
+struct Submenu(HMENU);
+
+unsafe impl Send for Submenu {}
+
+// Number of hidden items in front of a position in a menu.
+fn hidden_before(hidden: &HashMap<u32, HiddenItem>, menu: HMENU, position: u32) -> u32 {
+    hidden
+        .values()
+        .filter(|h| h.menu == menu && h.position < position)
+        .count() as u32
+}
+
+// Turns the position of an item shown in a menu into its position counting
+// the hidden items.
+fn full_position(hidden: &HashMap<u32, HiddenItem>, menu: HMENU, shown: u32) -> u32 {
+    let mut positions = hidden
+        .values()
+        .filter(|h| h.menu == menu)
+        .map(|h| h.position)
+        .collect::<Vec<_>>();
+    positions.sort_unstable();
+    let mut position = shown;
+    for p in positions {
+        if p <= position {
+            position += 1;
+        }
+    }
+    position
+}
+
+// Moves the hidden items of a menu after a position by an inserted or
+// removed item.
+fn shift_hidden(hidden: &mut HashMap<u32, HiddenItem>, menu: HMENU, from: u32, inserted: bool) {
+    for h in hidden
+        .values_mut()
+        .filter(|h| h.menu == menu && h.position >= from)
+    {
+        if inserted {
+            h.position += 1;
+        } else {
+            h.position -= 1;
+        }
+    }
+}
+
 struct WindowsLoopData {
     pub info: WindowInfo,
-    pub tx: Sender<SystrayEvent>,
+    pub tx: EventSender,
+    pub animation: RefCell<Option<Animation>>,
+    pub attention_icon: RefCell<Option<Animation>>,
+    pub status: Cell<WPARAM>,
+    // Id of the balloon shown, and its icon.
+    pub balloon: Cell<Option<u32>>,
+    pub balloon_icon: RefCell<Option<Animation>>,
+}
+
+// Sent to the window thread to change the icon, with a boxed Animation in
+// lParam and the frame duration in wParam, or to stop animating with a null
+// lParam. Every icon goes through the window thread, so an animation can't
+// overwrite an icon set after it.
+const WM_SET_ANIMATION: UINT = WM_USER + 2;
+// wParam is the Status, as STATUS_*.
+const WM_SET_STATUS: UINT = WM_USER + 3;
+// lParam is a boxed single frame Animation.
+const WM_SET_ATTENTION_ICON: UINT = WM_USER + 4;
+// lParam is a boxed Balloon, wParam its notification id.
+const WM_SHOW_BALLOON: UINT = WM_USER + 5;
+const ANIMATION_TIMER: usize = 1;
+
+const STATUS_PASSIVE: WPARAM = 0;
+const STATUS_ACTIVE: WPARAM = 1;
+const STATUS_NEEDS_ATTENTION: WPARAM = 2;
+
+// Icons shown by the window thread, which owns them. Static icons are single
+// frame animations.
+struct Animation {
+    icons: Vec<HICON>,
+    frame: usize,
+}
+
+// A notification on its way to the window thread, which keeps the icon for as
+// long as the balloon is up.
+struct Balloon {
+    nid: NOTIFYICONDATAW,
+    icon: Option<Animation>,
+}
+
+impl Drop for Animation {
+    fn drop(&mut self) {
+        for icon in &self.icons {
+            unsafe {
+                winuser::DestroyIcon(*icon);
+            }
+        }
+    }
 }
 
 unsafe fn get_win_os_error(msg: &str) -> Error {
     Error::OsError(format!("{}: {}", &msg, errhandlingapi::GetLastError()))
 }
 
+fn send_event(event: Event) {
+    WININFO_STASH.with(|stash| {
+        if let Some(stash) = stash.borrow().as_ref() {
+            stash.tx.send(event);
+        }
+    });
+}
+
+// Reports what happened to the balloon that is up, if any.
+fn end_balloon<F>(event: F)
+where
+    F: FnOnce(u32) -> Event,
+{
+    WININFO_STASH.with(|stash| {
+        if let Some(stash) = stash.borrow().as_ref() {
+            if let Some(id) = stash.balloon.take() {
+                stash.balloon_icon.borrow_mut().take();
+                stash.tx.send(event(id));
+            }
+        }
+    });
+}
+
+// Broadcast by Explorer when it (re)creates the taskbar, after which it has
+// forgotten every tray icon.
+fn taskbar_created_message() -> UINT {
+    static MESSAGE: OnceLock<UINT> = OnceLock::new();
+    *MESSAGE.get_or_init(|| unsafe {
+        winuser::RegisterWindowMessageW(to_wstring("TaskbarCreated").as_ptr())
+    })
+}
+
+pub fn is_tray_available() -> bool {
+    let class_name = to_wstring("Shell_TrayWnd");
+    unsafe { !winuser::FindWindowW(class_name.as_ptr(), std::ptr::null()).is_null() }
+}
+
+// Adds the icon back after an Explorer restart, with the current image and
+// visibility. The tooltip is left empty until it is set again.
+unsafe fn restore_icon(hwnd: HWND, stash: &WindowsLoopData) -> Result<(), Error> {
+    let mut nid = get_nid_struct(&hwnd);
+    nid.uFlags = NIF_MESSAGE | NIF_STATE;
+    nid.uCallbackMessage = WM_USER + 1;
+    nid.dwStateMask = NIS_HIDDEN;
+    if stash.status.get() == STATUS_PASSIVE {
+        nid.dwState = NIS_HIDDEN;
+    }
+    if shellapi::Shell_NotifyIconW(NIM_ADD, &mut nid as *mut NOTIFYICONDATAW) == 0 {
+        return Err(get_win_os_error("Error adding menu icon"));
+    }
+    show_current_icon(hwnd, stash)
+}
+
 unsafe extern "system" fn window_proc(
     h_wnd: HWND,
     msg: UINT,
@@ -66,24 +266,36 @@ unsafe extern "system" fn window_proc(
     l_param: LPARAM,
 ) -> LRESULT {
     if msg == winuser::WM_MENUCOMMAND {
-        WININFO_STASH.with(|stash| {
-            let stash = stash.borrow();
-            let stash = stash.as_ref();
-            if let Some(stash) = stash {
-                let menu_id = winuser::GetMenuItemID(stash.info.hmenu, w_param as i32) as i32;
-                if menu_id != -1 {
-                    stash
-                        .tx
-                        .send(SystrayEvent {
-                            menu_index: menu_id as u32,
-                        })
-                        .ok();
-                }
-            }
-        });
+        // With MNS_NOTIFYBYPOS, lParam is the menu (or submenu) that holds the
+        // selected item, and wParam is its position there.
+        let menu_id = winuser::GetMenuItemID(l_param as HMENU, w_param as i32) as i32;
+        if menu_id != -1 {
+            send_event(Event::MenuItemActivated {
+                index: menu_id as u32,
+            });
+        }
     }
 
     if msg == WM_USER + 1 {
+        match l_param as UINT {
+            winuser::WM_LBUTTONUP => send_event(Event::LeftClick),
+            winuser::WM_LBUTTONDBLCLK => send_event(Event::DoubleClick),
+            winuser::WM_MBUTTONUP => send_event(Event::MiddleClick),
+            NIN_BALLOONUSERCLICK => {
+                end_balloon(|id| Event::NotificationActivated { id, action: None })
+            }
+            // Sent both when the balloon times out and when the user closes
+            // it, which can't be told apart.
+            NIN_BALLOONTIMEOUT => end_balloon(|id| Event::NotificationClosed {
+                id,
+                reason: CloseReason::Unknown,
+            }),
+            NIN_BALLOONHIDE => end_balloon(|id| Event::NotificationClosed {
+                id,
+                reason: CloseReason::Closed,
+            }),
+            _ => {}
+        }
         if l_param as UINT == winuser::WM_LBUTTONUP || l_param as UINT == winuser::WM_RBUTTONUP {
             let mut p = POINT { x: 0, y: 0 };
             if winuser::GetCursorPos(&mut p as *mut POINT) == 0 {
@@ -94,6 +306,8 @@ unsafe extern "system" fn window_proc(
                 let stash = stash.borrow();
                 let stash = stash.as_ref();
                 if let Some(stash) = stash {
+                    stash.tx.send(Event::MenuOpened);
+                    // Blocks until the menu is closed.
                     winuser::TrackPopupMenu(
                         stash.info.hmenu,
                         0,
@@ -103,10 +317,46 @@ unsafe extern "system" fn window_proc(
                         h_wnd,
                         std::ptr::null_mut(),
                     );
+                    stash.tx.send(Event::MenuClosed);
                 }
             });
         }
     }
+    if msg == WM_SET_ANIMATION || msg == WM_SET_STATUS || msg == WM_SET_ATTENTION_ICON {
+        return WININFO_STASH.with(|stash| match stash.borrow().as_ref() {
+            Some(stash) => update_icon(h_wnd, stash, msg, w_param, l_param),
+            None => 0,
+        });
+    }
+    if msg == WM_SHOW_BALLOON {
+        return WININFO_STASH.with(|stash| match stash.borrow().as_ref() {
+            Some(stash) => {
+                let balloon = *Box::from_raw(l_param as *mut Balloon);
+                show_balloon(stash, w_param as u32, balloon)
+            }
+            None => 0,
+        });
+    }
+    if msg == winuser::WM_TIMER && w_param == ANIMATION_TIMER {
+        WININFO_STASH.with(|stash| {
+            if let Some(stash) = stash.borrow().as_ref() {
+                if let Some(a) = stash.animation.borrow_mut().as_mut() {
+                    a.frame = (a.frame + 1) % a.icons.len();
+                }
+                show_current_icon(h_wnd, stash).ok();
+            }
+        });
+    }
+    if msg == taskbar_created_message() {
+        WININFO_STASH.with(|stash| {
+            if let Some(stash) = stash.borrow().as_ref() {
+                if let Err(e) = restore_icon(h_wnd, stash) {
+                    log::debug!("Error restoring tray icon: {}", e);
+                }
+                stash.tx.send(Event::TrayHostChanged { available: true });
+            }
+        });
+    }
     if msg == winuser::WM_DESTROY {
         winuser::PostQuitMessage(0);
     }
@@ -155,7 +405,152 @@ fn get_menu_item_struct() -> MENUITEMINFOW {
     }
 }
 
-unsafe fn init_window() -> Result<WindowInfo, Error> {
+unsafe fn set_notify_icon(hwnd: HWND, icon: HICON) -> Result<(), Error> {
+    let mut nid = get_nid_struct(&hwnd);
+    nid.uFlags = NIF_ICON;
+    nid.hIcon = icon;
+    if shellapi::Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) == 0 {
+        return Err(get_win_os_error("Error setting icon"));
+    }
+    Ok(())
+}
+
+// Shows the attention icon while the status asks for it, and the current
+// frame otherwise.
+unsafe fn show_current_icon(hwnd: HWND, stash: &WindowsLoopData) -> Result<(), Error> {
+    if stash.status.get() == STATUS_NEEDS_ATTENTION {
+        if let Some(ref a) = *stash.attention_icon.borrow() {
+            return set_notify_icon(hwnd, a.icons[0]);
+        }
+    }
+    match *stash.animation.borrow() {
+        Some(ref a) => set_notify_icon(hwnd, a.icons[a.frame]),
+        None => Ok(()),
+    }
+}
+
+// Handles the WM_SET_* messages, returning 1 on success and 2 if the shell
+// rejected the change.
+unsafe fn update_icon(
+    hwnd: HWND,
+    stash: &WindowsLoopData,
+    msg: UINT,
+    w_param: WPARAM,
+    l_param: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_SET_ANIMATION => {
+            winuser::KillTimer(hwnd, ANIMATION_TIMER);
+            if l_param == 0 {
+                return 1;
+            }
+            let animation = *Box::from_raw(l_param as *mut Animation);
+            if animation.icons.len() > 1 {
+                winuser::SetTimer(hwnd, ANIMATION_TIMER, w_param as UINT, None);
+            }
+            *stash.animation.borrow_mut() = Some(animation);
+        }
+        WM_SET_ATTENTION_ICON => {
+            *stash.attention_icon.borrow_mut() = Some(*Box::from_raw(l_param as *mut Animation));
+        }
+        _ => {
+            stash.status.set(w_param);
+            let mut nid = get_nid_struct(&hwnd);
+            nid.uFlags = NIF_STATE;
+            nid.dwStateMask = NIS_HIDDEN;
+            if w_param == STATUS_PASSIVE {
+                nid.dwState = NIS_HIDDEN;
+            }
+            if shellapi::Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) == 0 {
+                return 2;
+            }
+        }
+    }
+    match show_current_icon(hwnd, stash) {
+        Ok(()) => 1,
+        Err(_) => 2,
+    }
+}
+
+// Handles WM_SHOW_BALLOON, returning 1 on success and 2 if the shell rejected
+// the balloon.
+unsafe fn show_balloon(stash: &WindowsLoopData, id: u32, mut balloon: Balloon) -> LRESULT {
+    if shellapi::Shell_NotifyIconW(NIM_MODIFY, &mut balloon.nid as *mut NOTIFYICONDATAW) == 0 {
+        return 2;
+    }
+    // Only one balloon is shown at a time, the new one replaced it.
+    if let Some(old) = stash.balloon.replace(Some(id)) {
+        stash.tx.send(Event::NotificationClosed {
+            id: old,
+            reason: CloseReason::Closed,
+        });
+    }
+    *stash.balloon_icon.borrow_mut() = balloon.icon;
+    1
+}
+
+unsafe fn create_icon(image: &IconImage) -> Result<HICON, Error> {
+    // CreateIcon wants BGRA pixels, and a mask with one bit per pixel and rows
+    // padded to 16 bits. The mask only matters where there is no alpha, but it
+    // should still match it.
+    let mut bgra = image.rgba.clone();
+    let width = image.width as usize;
+    let mask_stride = width.div_ceil(16) * 2;
+    let mut mask = vec![0u8; mask_stride * image.height as usize];
+    for (i, pixel) in bgra.chunks_mut(4).enumerate() {
+        pixel.swap(0, 2);
+        if pixel[3] == 0 {
+            let (x, y) = (i % width, i / width);
+            mask[y * mask_stride + x / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    let hicon = winuser::CreateIcon(
+        std::ptr::null_mut() as HINSTANCE,
+        image.width as i32,
+        image.height as i32,
+        1,
+        32,
+        mask.as_ptr(),
+        bgra.as_ptr(),
+    );
+    if hicon.is_null() {
+        return Err(get_win_os_error("Error creating icon"));
+    }
+    Ok(hicon)
+}
+
+unsafe fn create_menu() -> Result<HMENU, Error> {
+    let hmenu = winuser::CreatePopupMenu();
+    let m = MENUINFO {
+        cbSize: std::mem::size_of::<MENUINFO>() as DWORD,
+        fMask: MIM_APPLYTOSUBMENUS | MIM_STYLE,
+        dwStyle: MNS_NOTIFYBYPOS,
+        cyMax: 0 as UINT,
+        hbrBack: 0 as HBRUSH,
+        dwContextHelpID: 0 as DWORD,
+        dwMenuData: 0 as ULONG_PTR,
+    };
+    if winuser::SetMenuInfo(hmenu, &m as *const MENUINFO) == 0 {
+        return Err(get_win_os_error("Error setting up menu"));
+    }
+    Ok(hmenu)
+}
+
+// Copies the string into a fixed size field, cut short if it doesn't fit.
+fn copy_wstring(field: &mut [u16], s: &str) {
+    let ws = to_wstring(s);
+    let len = std::cmp::min(ws.len() - 1, field.len() - 1);
+    field[..len].copy_from_slice(&ws[..len]);
+}
+
+// szTip holds 127 characters plus the terminating null, longer tooltips are
+// cut off.
+fn set_nid_tip(nid: &mut NOTIFYICONDATAW, tooltip: &str) {
+    copy_wstring(&mut nid.szTip, tooltip);
+}
+
+// Also returns the initial icon, which the window thread owns.
+unsafe fn init_window(config: &ApplicationBuilder) -> Result<(WindowInfo, Option<Animation>), Error> {
     let class_name = to_wstring("my_window");
     let hinstance: HINSTANCE = libloaderapi::GetModuleHandleA(std::ptr::null_mut());
     let wnd = WNDCLASSW {
@@ -194,29 +589,37 @@ unsafe fn init_window() -> Result<WindowInfo, Error> {
     nid.uID = 0x1;
     nid.uFlags = NIF_MESSAGE;
     nid.uCallbackMessage = WM_USER + 1;
+    let mut icon = None;
+    if let Some(ref i) = config.icon {
+        let size = winuser::GetSystemMetrics(winuser::SM_CXSMICON);
+        let hicon = create_icon(i.best_for_size(size as u32))?;
+        nid.uFlags |= NIF_ICON;
+        nid.hIcon = hicon;
+        icon = Some(Animation {
+            icons: vec![hicon],
+            frame: 0,
+        });
+    }
+    if let Some(ref title) = config.title {
+        nid.uFlags |= NIF_TIP;
+        set_nid_tip(&mut nid, title);
+    }
     if shellapi::Shell_NotifyIconW(NIM_ADD, &mut nid as *mut NOTIFYICONDATAW) == 0 {
         return Err(get_win_os_error("Error adding menu icon"));
     }
     // Setup menu
-    let hmenu = winuser::CreatePopupMenu();
-    let m = MENUINFO {
-        cbSize: std::mem::size_of::<MENUINFO>() as DWORD,
-        fMask: MIM_APPLYTOSUBMENUS | MIM_STYLE,
-        dwStyle: MNS_NOTIFYBYPOS,
-        cyMax: 0 as UINT,
-        hbrBack: 0 as HBRUSH,
-        dwContextHelpID: 0 as DWORD,
-        dwMenuData: 0 as ULONG_PTR,
-    };
-    if winuser::SetMenuInfo(hmenu, &m as *const MENUINFO) == 0 {
-        return Err(get_win_os_error("Error setting up menu"));
-    }
+    let hmenu = create_menu()?;
 
-    Ok(WindowInfo {
-        hwnd: hwnd,
-        hmenu: hmenu,
-        hinstance: hinstance,
-    })
+    Ok((
+        WindowInfo {
+            hwnd,
+            hmenu,
+            hinstance,
+            hidden: Arc::new(Mutex::new(HashMap::new())),
+            submenus: Arc::new(Mutex::new(HashMap::new())),
+        },
+        icon,
+    ))
 }
 
 unsafe fn run_loop() {
@@ -247,16 +650,18 @@ pub struct Window {
 }
 
 impl Window {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<Window, Error> {
+    pub fn new(event_tx: EventSender, config: ApplicationBuilder) -> Result<Window, Error> {
         let (tx, rx) = channel();
         let windows_loop = thread::spawn(move || {
             unsafe {
-                let i = init_window();
+                let i = init_window(&config);
                 let k;
+                let icon;
                 match i {
-                    Ok(j) => {
+                    Ok((j, initial_icon)) => {
                         tx.send(Ok(j.clone())).ok();
                         k = j;
+                        icon = initial_icon;
                     }
                     Err(e) => {
                         // If creation didn't work, return out of the thread.
@@ -268,13 +673,26 @@ impl Window {
                     let data = WindowsLoopData {
                         info: k,
                         tx: event_tx,
+                        animation: RefCell::new(icon),
+                        attention_icon: RefCell::new(None),
+                        status: Cell::new(STATUS_ACTIVE),
+                        balloon: Cell::new(None),
+                        balloon_icon: RefCell::new(None),
                     };
                     (*stash.borrow_mut()) = Some(data);
                 });
                 run_loop();
+                WININFO_STASH.with(|stash| {
+                    if let Some(stash) = stash.borrow().as_ref() {
+                        stash.animation.borrow_mut().take();
+                        stash.attention_icon.borrow_mut().take();
+                        stash.balloon_icon.borrow_mut().take();
+                    }
+                });
+                send_event(Event::Shutdown);
             }
         });
-        let info = match rx.recv().unwrap() {
+        let info = match rx.recv().map_err(|_| Error::BackendGone)? {
             Ok(i) => i,
             Err(e) => {
                 return Err(e);
@@ -287,35 +705,205 @@ impl Window {
         Ok(w)
     }
 
-    pub fn quit(&mut self) {
+    pub fn handle(&self) -> Handle {
+        Handle {
+            info: self.info.clone(),
+        }
+    }
+
+    fn parent_menu(&self, parent: Option<u32>) -> Result<HMENU, Error> {
+        match parent {
+            None => Ok(self.info.hmenu),
+            Some(p) => match self.info.submenus.lock().unwrap().get(&p) {
+                Some(m) => Ok(m.0),
+                None => Err(Error::InvalidMenuItem(p)),
+            },
+        }
+    }
+
+    // Inserts an item into the parent menu, at the end unless a position is
+    // given. Positions are per menu, so the global item index can't be used
+    // as the insert position. They count the hidden items too.
+    fn insert_menu_item(
+        &self,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item: &MENUITEMINFOW,
+        err_msg: &str,
+    ) -> Result<(), Error> {
+        let hmenu = self.parent_menu(parent)?;
+        let mut hidden = self.info.hidden.lock().unwrap();
         unsafe {
-            winuser::PostMessageW(self.info.hwnd, WM_DESTROY, 0 as WPARAM, 0 as LPARAM);
+            let count = winuser::GetMenuItemCount(hmenu);
+            if count == -1 {
+                return Err(get_win_os_error(err_msg));
+            }
+            let count = count as UINT + hidden.values().filter(|h| h.menu == hmenu).count() as UINT;
+            let position = match position {
+                Some(p) if p < count => p,
+                _ => count,
+            };
+            let shown = position - hidden_before(&hidden, hmenu, position);
+            if winuser::InsertMenuItemW(hmenu, shown, TRUE, item as *const MENUITEMINFOW) == 0 {
+                return Err(get_win_os_error(err_msg));
+            }
+            shift_hidden(&mut hidden, hmenu, position, true);
+        }
+        Ok(())
+    }
+
+    // Drops the hidden items of a menu that is going away, with their
+    // submenus.
+    fn forget_hidden_in(&self, hidden: &mut HashMap<u32, HiddenItem>, hmenu: HMENU) {
+        let ids = hidden
+            .iter()
+            .filter(|(_, h)| h.menu == hmenu)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            let item = hidden.remove(&id).unwrap();
+            if !item.info.hSubMenu.is_null() {
+                self.forget_hidden_in(hidden, item.info.hSubMenu);
+                self.info.submenus.lock().unwrap().remove(&id);
+                unsafe {
+                    winuser::DestroyMenu(item.info.hSubMenu);
+                }
+            }
+        }
+    }
+
+    fn hide_menu_item(
+        &self,
+        hidden: &mut HashMap<u32, HiddenItem>,
+        item_idx: u32,
+    ) -> Result<(), Error> {
+        let (hmenu, shown) = self
+            .info
+            .find_menu_item(item_idx)
+            .ok_or(Error::InvalidMenuItem(item_idx))?;
+        let mut item = get_menu_item_struct();
+        item.fMask = MIIM_FTYPE | MIIM_STRING | MIIM_ID | MIIM_STATE | MIIM_SUBMENU;
+        let mut label;
+        unsafe {
+            // Asked once for the length of the label, then for the label.
+            if winuser::GetMenuItemInfoW(hmenu, shown, TRUE, &mut item) == 0 {
+                return Err(get_win_os_error("Error hiding menu item"));
+            }
+            label = vec![0u16; item.cch as usize + 1];
+            item.dwTypeData = label.as_mut_ptr();
+            item.cch += 1;
+            if winuser::GetMenuItemInfoW(hmenu, shown, TRUE, &mut item) == 0 {
+                return Err(get_win_os_error("Error hiding menu item"));
+            }
+            // Unlike DeleteMenu, this keeps the submenu of the item.
+            if winuser::RemoveMenu(hmenu, shown, MF_BYPOSITION) == 0 {
+                return Err(get_win_os_error("Error hiding menu item"));
+            }
+        }
+        item.dwTypeData = std::ptr::null_mut();
+        let position = full_position(hidden, hmenu, shown);
+        hidden.insert(
+            item_idx,
+            HiddenItem {
+                menu: hmenu,
+                position,
+                info: item,
+                label,
+            },
+        );
+        Ok(())
+    }
+
+    fn show_menu_item(
+        &self,
+        hidden: &mut HashMap<u32, HiddenItem>,
+        item_idx: u32,
+    ) -> Result<(), Error> {
+        let mut item = match hidden.remove(&item_idx) {
+            Some(item) => item,
+            None => return Ok(()),
+        };
+        let shown = item.position - hidden_before(hidden, item.menu, item.position);
+        item.info.dwTypeData = item.label.as_mut_ptr();
+        unsafe {
+            if winuser::InsertMenuItemW(item.menu, shown, TRUE, &item.info as *const MENUITEMINFOW)
+                == 0
+            {
+                let err = get_win_os_error("Error showing menu item");
+                hidden.insert(item_idx, item);
+                return Err(err);
+            }
         }
+        Ok(())
+    }
+}
+
+impl Backend for Window {
+    fn quit(&mut self) {
+        self.handle().quit();
         if let Some(t) = self.windows_loop.take() {
             t.join().ok();
         }
     }
 
-    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
-        // Add Tooltip
-        log::debug!("Setting tooltip to {}", tooltip);
-        // Gross way to convert String to [i8; 128]
-        // TODO: Clean up conversion, test for length so we don't panic at runtime
-        let tt = tooltip.as_bytes().clone();
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.handle().set_tooltip(tooltip)
+    }
+
+    // Shown as a balloon from the tray icon, which has no actions. Since
+    // Vista the system decides how long balloons stay up.
+    fn notify(&self, notification: &Notification) -> Result<u32, Error> {
+        static NOTIFICATION_COUNT: AtomicU32 = AtomicU32::new(0);
+
         let mut nid = get_nid_struct(&self.info.hwnd);
-        for i in 0..tt.len() {
-            nid.szTip[i] = tt[i] as u16;
+        nid.uFlags = NIF_INFO;
+        copy_wstring(&mut nid.szInfoTitle, &notification.title);
+        copy_wstring(&mut nid.szInfo, &notification.body);
+        nid.dwInfoFlags = match notification.urgency {
+            Urgency::Low => NIIF_INFO | NIIF_NOSOUND,
+            Urgency::Normal => NIIF_INFO,
+            Urgency::Critical => NIIF_WARNING,
+        };
+        if let Some(timeout) = notification.timeout {
+            unsafe {
+                *nid.u.uTimeout_mut() = timeout.as_millis().min(u32::MAX as u128) as u32;
+            }
         }
-        nid.uFlags = NIF_TIP;
-        unsafe {
-            if shellapi::Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) == 0 {
-                return Err(get_win_os_error("Error setting tooltip"));
+        let icon = match notification.icon {
+            Some(ref icon) => {
+                let size = unsafe { winuser::GetSystemMetrics(winuser::SM_CXICON) };
+                let hicon = unsafe { create_icon(icon.best_for_size(size as u32))? };
+                nid.dwInfoFlags = (nid.dwInfoFlags & !NIIF_ICON_MASK) | NIIF_USER | NIIF_LARGE_ICON;
+                nid.hBalloonIcon = hicon;
+                Some(Animation {
+                    icons: vec![hicon],
+                    frame: 0,
+                })
+            }
+            None => None,
+        };
+        let id = NOTIFICATION_COUNT.fetch_add(1, Ordering::SeqCst) + 1;
+        let balloon = Box::into_raw(Box::new(Balloon { nid, icon })) as LPARAM;
+        let result = unsafe {
+            winuser::SendMessageW(self.info.hwnd, WM_SHOW_BALLOON, id as WPARAM, balloon)
+        };
+        match result {
+            1 => Ok(id),
+            0 => {
+                drop(unsafe { Box::from_raw(balloon as *mut Balloon) });
+                Err(Error::BackendGone)
             }
+            _ => Err(Error::OsError("Error showing notification".to_owned())),
         }
-        Ok(())
     }
 
-    pub fn add_menu_entry(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+    fn add_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
         let mut st = to_wstring(item_name);
         let mut item = get_menu_item_struct();
         item.fMask = MIIM_FTYPE | MIIM_STRING | MIIM_ID | MIIM_STATE;
@@ -323,44 +911,152 @@ impl Window {
         item.wID = item_idx;
         item.dwTypeData = st.as_mut_ptr();
         item.cch = (item_name.len() * 2) as u32;
-        unsafe {
-            if winuser::InsertMenuItemW(self.info.hmenu, item_idx, 1, &item as *const MENUITEMINFOW)
-                == 0
-            {
-                return Err(get_win_os_error("Error inserting menu item"));
+        self.insert_menu_item(parent, position, &item, "Error inserting menu item")
+    }
+
+    fn add_menu_separator(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+    ) -> Result<(), Error> {
+        let mut item = get_menu_item_struct();
+        item.fMask = MIIM_FTYPE | MIIM_ID;
+        item.fType = MFT_SEPARATOR;
+        item.wID = item_idx;
+        self.insert_menu_item(parent, position, &item, "Error inserting separator")
+    }
+
+    fn add_submenu(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        let submenu = unsafe { create_menu()? };
+        let mut st = to_wstring(item_name);
+        let mut item = get_menu_item_struct();
+        item.fMask = MIIM_FTYPE | MIIM_STRING | MIIM_ID | MIIM_SUBMENU;
+        item.fType = MFT_STRING;
+        item.wID = item_idx;
+        item.hSubMenu = submenu;
+        item.dwTypeData = st.as_mut_ptr();
+        item.cch = (item_name.len() * 2) as u32;
+        if let Err(e) = self.insert_menu_item(parent, position, &item, "Error inserting submenu") {
+            unsafe {
+                winuser::DestroyMenu(submenu);
             }
+            return Err(e);
         }
+        self.info
+            .submenus
+            .lock()
+            .unwrap()
+            .insert(item_idx, Submenu(submenu));
         Ok(())
     }
 
-    pub fn add_menu_separator(&self, item_idx: u32) -> Result<(), Error> {
+    fn add_check_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+        checked: bool,
+        radio: bool,
+    ) -> Result<(), Error> {
+        let mut st = to_wstring(item_name);
         let mut item = get_menu_item_struct();
-        item.fMask = MIIM_FTYPE;
-        item.fType = MFT_SEPARATOR;
+        item.fMask = MIIM_FTYPE | MIIM_STRING | MIIM_ID | MIIM_STATE;
+        item.fType = if radio {
+            MFT_STRING | MFT_RADIOCHECK
+        } else {
+            MFT_STRING
+        };
+        if checked {
+            item.fState = MFS_CHECKED;
+        }
         item.wID = item_idx;
+        item.dwTypeData = st.as_mut_ptr();
+        item.cch = (item_name.len() * 2) as u32;
+        self.insert_menu_item(parent, position, &item, "Error inserting check menu item")
+    }
+
+    fn set_menu_entry_checked(&self, item_idx: u32, checked: bool) -> Result<(), Error> {
+        if let Some(item) = self.info.hidden.lock().unwrap().get_mut(&item_idx) {
+            item.info.fState = if checked {
+                item.info.fState | MFS_CHECKED
+            } else {
+                item.info.fState & !MFS_CHECKED
+            };
+            return Ok(());
+        }
+        let (hmenu, position) = self
+            .info
+            .find_menu_item(item_idx)
+            .ok_or(Error::InvalidMenuItem(item_idx))?;
+        let flags = if checked { MF_CHECKED } else { MF_UNCHECKED };
         unsafe {
-            if winuser::InsertMenuItemW(self.info.hmenu, item_idx, 1, &item as *const MENUITEMINFOW)
-                == 0
-            {
-                return Err(get_win_os_error("Error inserting separator"));
+            if winuser::CheckMenuItem(hmenu, position, MF_BYPOSITION | flags) == u32::MAX {
+                return Err(get_win_os_error("Error setting menu item check state"));
             }
         }
         Ok(())
     }
 
-    fn set_icon(&self, icon: HICON) -> Result<(), Error> {
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), Error> {
+        let mut hidden = self.info.hidden.lock().unwrap();
+        if let Some(item) = hidden.remove(&item_idx) {
+            shift_hidden(&mut hidden, item.menu, item.position, false);
+            if !item.info.hSubMenu.is_null() {
+                self.forget_hidden_in(&mut hidden, item.info.hSubMenu);
+                unsafe {
+                    winuser::DestroyMenu(item.info.hSubMenu);
+                }
+            }
+            self.info.submenus.lock().unwrap().remove(&item_idx);
+            return Ok(());
+        }
+        let (hmenu, shown) = match self.info.find_menu_item(item_idx) {
+            Some(found) => found,
+            None => return Err(Error::InvalidMenuItem(item_idx)),
+        };
+        let position = full_position(&hidden, hmenu, shown);
+        let submenu = self.info.submenus.lock().unwrap().remove(&item_idx);
+        if let Some(submenu) = submenu {
+            self.forget_hidden_in(&mut hidden, submenu.0);
+        }
         unsafe {
-            let mut nid = get_nid_struct(&self.info.hwnd);
-            nid.uFlags = NIF_ICON;
-            nid.hIcon = icon;
-            if shellapi::Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) == 0 {
-                return Err(get_win_os_error("Error setting icon"));
+            // DeleteMenu also destroys the submenu of the item, if any.
+            if winuser::DeleteMenu(hmenu, shown, MF_BYPOSITION) == 0 {
+                return Err(get_win_os_error("Error removing menu item"));
             }
         }
+        shift_hidden(&mut hidden, hmenu, position, false);
         Ok(())
     }
 
-    pub fn set_icon_from_resource(&self, resource_name: &str) -> Result<(), Error> {
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.handle().set_menu_entry_enabled(item_idx, enabled)
+    }
+
+    fn set_menu_entry_visible(&self, item_idx: u32, visible: bool) -> Result<(), Error> {
+        let mut hidden = self.info.hidden.lock().unwrap();
+        if visible {
+            self.show_menu_item(&mut hidden, item_idx)
+        } else if hidden.contains_key(&item_idx) {
+            Ok(())
+        } else {
+            self.hide_menu_item(&mut hidden, item_idx)
+        }
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.handle().set_menu_entry_label(item_idx, item_name)
+    }
+
+    fn set_icon_from_resource(&self, resource_name: &str) -> Result<(), Error> {
         let icon;
         unsafe {
             icon = winuser::LoadImageW(
@@ -375,29 +1071,28 @@ impl Window {
                 return Err(get_win_os_error("Error setting icon from resource"));
             }
         }
-        self.set_icon(icon)
+        self.handle().set_hicon(icon)
     }
 
-    pub fn set_icon_from_file(&self, icon_file: &str) -> Result<(), Error> {
-        let wstr_icon_file = to_wstring(&icon_file);
-        let hicon;
-        unsafe {
-            hicon = winuser::LoadImageW(
-                std::ptr::null_mut() as HINSTANCE,
-                wstr_icon_file.as_ptr(),
-                IMAGE_ICON,
-                64,
-                64,
-                LR_LOADFROMFILE,
-            ) as HICON;
-            if hicon == std::ptr::null_mut() as HICON {
-                return Err(get_win_os_error("Error setting icon from file"));
-            }
-        }
-        self.set_icon(hicon)
+    fn set_icon_from_file(&self, icon_file: &str) -> Result<(), Error> {
+        self.handle().set_icon_from_file(icon_file)
     }
 
-    pub fn set_icon_from_buffer(
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.handle().set_icon_from_name(name)
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.handle().set_icon(icon)
+    }
+
+    fn set_icon_theme_path(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::Unsupported {
+            feature: "icon theme paths",
+        })
+    }
+
+    fn set_icon_from_buffer(
         &self,
         buffer: &[u8],
         width: u32,
@@ -431,13 +1126,16 @@ impl Window {
                 return Err(unsafe { get_win_os_error("Cannot load icon from the buffer") });
             }
 
-            self.set_icon(hicon)
+            self.handle().set_hicon(hicon)
         } else {
             Err(unsafe { get_win_os_error("Error setting icon from buffer") })
         }
     }
 
-    pub fn shutdown(&self) -> Result<(), Error> {
+    fn shutdown(&self) -> Result<(), Error> {
+        // The window may already be gone, in which case there is nothing to
+        // stop.
+        self.handle().stop_animation().ok();
         unsafe {
             let mut nid = get_nid_struct(&self.info.hwnd);
             nid.uFlags = NIF_ICON;
@@ -447,6 +1145,202 @@ impl Window {
         }
         Ok(())
     }
+
+    fn handle(&self) -> Arc<dyn BackendHandle> {
+        Arc::new(Window::handle(self))
+    }
+}
+
+// The shell and menu calls below don't need to be made from the window
+// thread, so the handle can be used from anywhere.
+#[derive(Clone)]
+pub struct Handle {
+    info: WindowInfo,
+}
+
+impl Handle {
+    // Takes ownership of the icon.
+    fn set_hicon(&self, icon: HICON) -> Result<(), Error> {
+        self.show_icons(vec![icon], 0)
+    }
+
+    // Hands the icons over to the window thread, see WM_SET_ANIMATION.
+    fn show_icons(&self, icons: Vec<HICON>, interval: u32) -> Result<(), Error> {
+        let animation = Animation { icons, frame: 0 };
+        self.send_icon_message(WM_SET_ANIMATION, interval as WPARAM, Some(animation))
+    }
+
+    // Sends one of the WM_SET_* messages. The window thread takes over the
+    // icons, unless the window is gone.
+    fn send_icon_message(
+        &self,
+        msg: UINT,
+        w_param: WPARAM,
+        icons: Option<Animation>,
+    ) -> Result<(), Error> {
+        let l_param = match icons {
+            Some(a) => Box::into_raw(Box::new(a)) as LPARAM,
+            None => 0,
+        };
+        let result = unsafe { winuser::SendMessageW(self.info.hwnd, msg, w_param, l_param) };
+        match result {
+            1 => Ok(()),
+            0 => {
+                if l_param != 0 {
+                    drop(unsafe { Box::from_raw(l_param as *mut Animation) });
+                }
+                Err(Error::BackendGone)
+            }
+            _ => Err(Error::OsError("Error updating tray icon".to_owned())),
+        }
+    }
+}
+
+impl BackendHandle for Handle {
+    fn quit(&self) {
+        unsafe {
+            winuser::PostMessageW(self.info.hwnd, WM_DESTROY, 0 as WPARAM, 0 as LPARAM);
+        }
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        // Add Tooltip
+        log::debug!("Setting tooltip to {}", tooltip);
+        let mut nid = get_nid_struct(&self.info.hwnd);
+        set_nid_tip(&mut nid, tooltip);
+        nid.uFlags = NIF_TIP;
+        unsafe {
+            if shellapi::Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) == 0 {
+                return Err(get_win_os_error("Error setting tooltip"));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        if let Some(item) = self.info.hidden.lock().unwrap().get_mut(&item_idx) {
+            item.info.fState = if enabled {
+                item.info.fState & !MFS_DISABLED
+            } else {
+                item.info.fState | MFS_DISABLED
+            };
+            return Ok(());
+        }
+        let (hmenu, position) = self
+            .info
+            .find_menu_item(item_idx)
+            .ok_or(Error::InvalidMenuItem(item_idx))?;
+        let flags = if enabled { MF_ENABLED } else { MF_GRAYED };
+        unsafe {
+            if winuser::EnableMenuItem(hmenu, position, MF_BYPOSITION | flags) == -1 {
+                return Err(get_win_os_error("Error setting menu item state"));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        if let Some(item) = self.info.hidden.lock().unwrap().get_mut(&item_idx) {
+            item.label = to_wstring(item_name);
+            return Ok(());
+        }
+        let (hmenu, position) = self
+            .info
+            .find_menu_item(item_idx)
+            .ok_or(Error::InvalidMenuItem(item_idx))?;
+        let mut st = to_wstring(item_name);
+        let mut item = get_menu_item_struct();
+        item.fMask = MIIM_STRING;
+        item.dwTypeData = st.as_mut_ptr();
+        item.cch = (item_name.len() * 2) as u32;
+        unsafe {
+            if winuser::SetMenuItemInfoW(hmenu, position, TRUE, &item as *const MENUITEMINFOW) == 0 {
+                return Err(get_win_os_error("Error setting menu item label"));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_animated_icon(&self, frames: &[Icon], frame_duration: Duration) -> Result<(), Error> {
+        let size = unsafe { winuser::GetSystemMetrics(winuser::SM_CXSMICON) };
+        let mut icons = vec![];
+        for frame in frames {
+            match unsafe { create_icon(frame.best_for_size(size as u32)) } {
+                Ok(icon) => icons.push(icon),
+                Err(e) => {
+                    for icon in icons {
+                        unsafe {
+                            winuser::DestroyIcon(icon);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let interval = frame_duration.as_millis().clamp(1, u32::MAX as u128) as u32;
+        self.show_icons(icons, interval)
+    }
+
+    fn stop_animation(&self) -> Result<(), Error> {
+        self.send_icon_message(WM_SET_ANIMATION, 0, None)
+    }
+
+    fn set_label(&self, _label: &str, _guide: &str) -> Result<(), Error> {
+        Err(Error::Unsupported { feature: "labels" })
+    }
+
+    // Windows has no status for tray icons, passive icons are hidden and the
+    // attention icon replaces the icon while it needs attention.
+    fn set_status(&self, status: Status) -> Result<(), Error> {
+        let status = match status {
+            Status::Passive => STATUS_PASSIVE,
+            Status::Active => STATUS_ACTIVE,
+            Status::NeedsAttention => STATUS_NEEDS_ATTENTION,
+        };
+        self.send_icon_message(WM_SET_STATUS, status, None)
+    }
+
+    fn set_attention_icon(&self, icon: &Icon) -> Result<(), Error> {
+        let size = unsafe { winuser::GetSystemMetrics(winuser::SM_CXSMICON) };
+        let hicon = unsafe { create_icon(icon.best_for_size(size as u32))? };
+        let icons = Animation {
+            icons: vec![hicon],
+            frame: 0,
+        };
+        self.send_icon_message(WM_SET_ATTENTION_ICON, 0, Some(icons))
+    }
+
+    fn set_icon_from_file(&self, icon_file: &str) -> Result<(), Error> {
+        let wstr_icon_file = to_wstring(&icon_file);
+        let hicon;
+        unsafe {
+            hicon = winuser::LoadImageW(
+                std::ptr::null_mut() as HINSTANCE,
+                wstr_icon_file.as_ptr(),
+                IMAGE_ICON,
+                64,
+                64,
+                LR_LOADFROMFILE,
+            ) as HICON;
+            if hicon == std::ptr::null_mut() as HICON {
+                return Err(get_win_os_error("Error setting icon from file"));
+            }
+        }
+        self.set_hicon(hicon)
+    }
+
+    // Windows has no icon themes.
+    fn set_icon_from_name(&self, _name: &str) -> Result<(), Error> {
+        Err(Error::Unsupported {
+            feature: "icons by name",
+        })
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        let size = unsafe { winuser::GetSystemMetrics(winuser::SM_CXSMICON) };
+        let hicon = unsafe { create_icon(icon.best_for_size(size as u32))? };
+        self.set_hicon(hicon)
+    }
 }
 
 impl Drop for Window {
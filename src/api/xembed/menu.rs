@@ -0,0 +1,633 @@
+// Popup menus for the XEmbed backend. Trays only dock the icon, so the menu
+// is drawn by hand in override-redirect windows with the server's core font,
+// with cascading windows for submenus and a pointer grab while it is open.
+
+use super::{x11_error, Display};
+use crate::Error;
+use std::collections::HashMap;
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{
+        Arc as XArc, ChangeGCAux, Char2b, ConfigureWindowAux, ConnectionExt, CoordMode, EventMask,
+        GrabMode, GrabStatus, Point, PolyShape, Rectangle, Window,
+    },
+    CURRENT_TIME, NONE,
+};
+
+const BORDER: u16 = 1;
+// Room for check marks left of the label, and submenu arrows right of it.
+const MARK_WIDTH: u16 = 20;
+const ARROW_WIDTH: u16 = 20;
+const ROW_PADDING: u16 = 3;
+const SEPARATOR_HEIGHT: u16 = 7;
+const MIN_WIDTH: u16 = 80;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    Standard,
+    Separator,
+    Submenu,
+    Check,
+    Radio,
+}
+
+pub(crate) struct Entry {
+    pub kind: EntryKind,
+    pub label: String,
+    pub enabled: bool,
+    pub visible: bool,
+    pub checked: bool,
+    parent: Option<u32>,
+    children: Vec<u32>,
+}
+
+impl Entry {
+    pub fn new(kind: EntryKind, label: &str) -> Entry {
+        Entry {
+            kind,
+            label: label.to_owned(),
+            enabled: true,
+            visible: true,
+            checked: false,
+            parent: None,
+            children: vec![],
+        }
+    }
+
+    // Plain, check and radio items that can be clicked right now.
+    fn activatable(&self) -> bool {
+        self.enabled && self.kind != EntryKind::Separator && self.kind != EntryKind::Submenu
+    }
+}
+
+// Menu entries by index, and the order of entries in each menu.
+#[derive(Default)]
+pub(crate) struct MenuTree {
+    entries: HashMap<u32, Entry>,
+    root: Vec<u32>,
+}
+
+impl MenuTree {
+    pub fn get(&self, idx: u32) -> Option<&Entry> {
+        self.entries.get(&idx)
+    }
+
+    pub fn get_mut(&mut self, idx: u32) -> Option<&mut Entry> {
+        self.entries.get_mut(&idx)
+    }
+
+    // Adds the entry at `position` in its parent, or at the end. Returns
+    // false if the parent is not a known submenu.
+    pub fn insert(
+        &mut self,
+        idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        mut entry: Entry,
+    ) -> bool {
+        self.remove(idx);
+        let siblings = match parent {
+            None => &mut self.root,
+            Some(p) => match self.entries.get_mut(&p) {
+                Some(e) if e.kind == EntryKind::Submenu => &mut e.children,
+                _ => return false,
+            },
+        };
+        let position = position
+            .map(|p| p as usize)
+            .unwrap_or(siblings.len())
+            .min(siblings.len());
+        siblings.insert(position, idx);
+        entry.parent = parent;
+        self.entries.insert(idx, entry);
+        true
+    }
+
+    // Removes the entry and everything below it.
+    pub fn remove(&mut self, idx: u32) {
+        let entry = match self.entries.remove(&idx) {
+            Some(e) => e,
+            None => return,
+        };
+        for child in entry.children {
+            self.remove(child);
+        }
+        match entry.parent {
+            None => self.root.retain(|&i| i != idx),
+            Some(p) => {
+                if let Some(parent) = self.entries.get_mut(&p) {
+                    parent.children.retain(|&i| i != idx);
+                }
+            }
+        }
+    }
+
+    // Visible entries of the root menu, or of a submenu.
+    fn visible_children(&self, parent: Option<u32>) -> Vec<u32> {
+        let children = match parent {
+            None => &self.root,
+            Some(p) => match self.entries.get(&p) {
+                Some(e) => &e.children,
+                None => return vec![],
+            },
+        };
+        children
+            .iter()
+            .copied()
+            .filter(|i| self.entries.get(i).map(|e| e.visible).unwrap_or(false))
+            .collect()
+    }
+}
+
+// Core fonts take UCS-2, anything beyond is shown as '?'. Text requests are
+// limited to 255 characters.
+pub(crate) fn text16(text: &str) -> Vec<Char2b> {
+    text.chars()
+        .take(255)
+        .map(|c| {
+            let c = if (c as u32) > 0xffff {
+                '?' as u32
+            } else {
+                c as u32
+            };
+            Char2b {
+                byte1: (c >> 8) as u8,
+                byte2: c as u8,
+            }
+        })
+        .collect()
+}
+
+struct Row {
+    idx: u32,
+    y: u16,
+    height: u16,
+}
+
+// One open menu window, showing the root menu or a submenu.
+struct Popup {
+    window: Window,
+    parent: Option<u32>,
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+    rows: Vec<Row>,
+    hover: Option<usize>,
+}
+
+impl Popup {
+    fn contains(&self, x: i16, y: i16) -> bool {
+        let (x, y) = (
+            i32::from(x) - i32::from(self.x),
+            i32::from(y) - i32::from(self.y),
+        );
+        let (width, height) = (
+            i32::from(self.width + 2 * BORDER),
+            i32::from(self.height + 2 * BORDER),
+        );
+        x >= 0 && y >= 0 && x < width && y < height
+    }
+
+    fn row_at(&self, y: i16) -> Option<usize> {
+        let y = i32::from(y) - i32::from(self.y) - i32::from(BORDER);
+        self.rows
+            .iter()
+            .position(|r| y >= i32::from(r.y) && y < i32::from(r.y + r.height))
+    }
+}
+
+// The stack of open menus, the root menu first.
+#[derive(Default)]
+pub(crate) struct Menus {
+    popups: Vec<Popup>,
+}
+
+impl Menus {
+    pub fn is_open(&self) -> bool {
+        !self.popups.is_empty()
+    }
+
+    pub fn owns(&self, window: Window) -> bool {
+        self.popups.iter().any(|p| p.window == window)
+    }
+
+    // Opens the root menu with its corner at the pointer, and grabs the
+    // pointer and keyboard so clicks elsewhere close it. Returns false if
+    // there is nothing to show.
+    pub fn open(&mut self, d: &Display, tree: &MenuTree, x: i16, y: i16) -> Result<bool, Error> {
+        self.close(d)?;
+        // One pixel off, so the release that opened the menu is not taken
+        // for a click on its first item.
+        if !self.push(d, tree, None, x + 1, y + 1, Some(y))? {
+            return Ok(false);
+        }
+        let window = self.popups[0].window;
+        let mask = EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE | EventMask::POINTER_MOTION;
+        let grab = d
+            .conn
+            .grab_pointer(
+                false,
+                window,
+                mask,
+                GrabMode::ASYNC,
+                GrabMode::ASYNC,
+                NONE,
+                NONE,
+                CURRENT_TIME,
+            )
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        if grab.status != GrabStatus::SUCCESS {
+            log::debug!(
+                "Cannot grab the pointer for the tray menu: {:?}",
+                grab.status
+            );
+        }
+        d.conn
+            .grab_keyboard(
+                false,
+                window,
+                CURRENT_TIME,
+                GrabMode::ASYNC,
+                GrabMode::ASYNC,
+            )
+            .map_err(x11_error)?;
+        d.conn.flush().map_err(x11_error)?;
+        Ok(true)
+    }
+
+    pub fn close(&mut self, d: &Display) -> Result<(), Error> {
+        if self.popups.is_empty() {
+            return Ok(());
+        }
+        self.close_from(d, 0)?;
+        d.conn.ungrab_pointer(CURRENT_TIME).map_err(x11_error)?;
+        d.conn.ungrab_keyboard(CURRENT_TIME).map_err(x11_error)?;
+        d.conn.flush().map_err(x11_error)
+    }
+
+    // Closes the menu at `level` and the submenus opened from it.
+    fn close_from(&mut self, d: &Display, level: usize) -> Result<(), Error> {
+        while self.popups.len() > level {
+            if let Some(popup) = self.popups.pop() {
+                d.conn.destroy_window(popup.window).map_err(x11_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Rows and size of the menu below `parent`.
+    fn layout(
+        d: &Display,
+        tree: &MenuTree,
+        parent: Option<u32>,
+    ) -> Result<(Vec<Row>, u16, u16), Error> {
+        let mut rows = vec![];
+        let mut width = MIN_WIDTH;
+        let mut y = 0;
+        for idx in tree.visible_children(parent) {
+            let entry = match tree.get(idx) {
+                Some(e) => e,
+                None => continue,
+            };
+            let height = if entry.kind == EntryKind::Separator {
+                SEPARATOR_HEIGHT
+            } else {
+                let text = d.text_width(&entry.label)?;
+                width = width.max(MARK_WIDTH + text + ARROW_WIDTH);
+                d.font.height() + 2 * ROW_PADDING
+            };
+            rows.push(Row { idx, y, height });
+            y += height;
+        }
+        Ok((rows, width, y))
+    }
+
+    // Opens the menu below `parent` at the given position, moved to stay on
+    // screen. Menus that don't fit below `flip_y` are shown above it.
+    fn push(
+        &mut self,
+        d: &Display,
+        tree: &MenuTree,
+        parent: Option<u32>,
+        x: i16,
+        y: i16,
+        flip_y: Option<i16>,
+    ) -> Result<bool, Error> {
+        let (rows, width, height) = Menus::layout(d, tree, parent)?;
+        if rows.is_empty() {
+            return Ok(false);
+        }
+        let (outer_width, outer_height) = (
+            i32::from(width + 2 * BORDER),
+            i32::from(height + 2 * BORDER),
+        );
+        let (screen_width, screen_height) = (i32::from(d.width), i32::from(d.height));
+        let mut x = i32::from(x);
+        let mut y = i32::from(y);
+        if x + outer_width > screen_width {
+            x = match (parent, self.popups.last()) {
+                // Cascade to the left of the parent menu instead.
+                (Some(_), Some(p)) => i32::from(p.x) - outer_width + 2,
+                _ => screen_width - outer_width,
+            };
+        }
+        if y + outer_height > screen_height {
+            y = match flip_y {
+                Some(flip_y) if i32::from(flip_y) - outer_height >= 0 => {
+                    i32::from(flip_y) - outer_height
+                }
+                _ => screen_height - outer_height,
+            };
+        }
+        let (x, y) = (x.max(0) as i16, y.max(0) as i16);
+
+        let window = d.popup(x, y, width, height, d.atoms._NET_WM_WINDOW_TYPE_POPUP_MENU)?;
+        self.popups.push(Popup {
+            window,
+            parent,
+            x,
+            y,
+            width,
+            height,
+            rows,
+            hover: None,
+        });
+        Ok(true)
+    }
+
+    fn hit(&self, x: i16, y: i16) -> Option<(usize, Option<usize>)> {
+        // Later submenus are drawn above their parents.
+        self.popups
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, p)| p.contains(x, y))
+            .map(|(level, p)| (level, p.row_at(y)))
+    }
+
+    fn set_hover(
+        &mut self,
+        d: &Display,
+        tree: &MenuTree,
+        level: usize,
+        row: Option<usize>,
+    ) -> Result<(), Error> {
+        if self.popups[level].hover != row {
+            self.popups[level].hover = row;
+            self.paint(d, tree, self.popups[level].window)?;
+        }
+        Ok(())
+    }
+
+    // Follows the pointer, highlighting the entry below it and opening the
+    // submenu it points to.
+    pub fn motion(&mut self, d: &Display, tree: &MenuTree, x: i16, y: i16) -> Result<(), Error> {
+        if self.popups.is_empty() {
+            return Ok(());
+        }
+        let (level, row) = match self.hit(x, y) {
+            Some((level, Some(row))) => (level, row),
+            Some((level, None)) => return self.set_hover(d, tree, level, None),
+            None => {
+                // Keep the entry a submenu was opened from highlighted.
+                let level = self.popups.len() - 1;
+                return self.set_hover(d, tree, level, None);
+            }
+        };
+        self.set_hover(d, tree, level, Some(row))?;
+        let idx = self.popups[level].rows[row].idx;
+        let submenu = tree
+            .get(idx)
+            .map(|e| e.kind == EntryKind::Submenu && e.enabled)
+            .unwrap_or(false);
+        let shown = self
+            .popups
+            .get(level + 1)
+            .map(|p| p.parent == Some(idx))
+            .unwrap_or(false);
+        if !shown {
+            self.close_from(d, level + 1)?;
+            if submenu {
+                let parent = &self.popups[level];
+                let x = parent.x + (parent.width + BORDER) as i16 - 2;
+                let y = parent.y + parent.rows[row].y as i16;
+                self.push(d, tree, Some(idx), x, y, None)?;
+            }
+        }
+        d.conn.flush().map_err(x11_error)
+    }
+
+    // Returns false if the click was outside every menu, which closes them.
+    pub fn press(&self, x: i16, y: i16) -> bool {
+        self.hit(x, y).is_some()
+    }
+
+    // The entry to activate if the button went up on one.
+    pub fn release(&self, tree: &MenuTree, x: i16, y: i16) -> Option<u32> {
+        let (level, row) = match self.hit(x, y) {
+            Some((level, Some(row))) => (level, row),
+            _ => return None,
+        };
+        let idx = self.popups[level].rows[row].idx;
+        match tree.get(idx) {
+            Some(entry) if entry.activatable() => Some(idx),
+            _ => None,
+        }
+    }
+
+    // Lays the open menus out again after the menu changed, closing those
+    // whose entry went away.
+    pub fn refresh(&mut self, d: &Display, tree: &MenuTree) -> Result<(), Error> {
+        for level in 0..self.popups.len() {
+            let parent = self.popups[level].parent;
+            let gone = match parent {
+                Some(p) => tree
+                    .get(p)
+                    .map(|e| !e.visible || !e.enabled || e.kind != EntryKind::Submenu)
+                    .unwrap_or(true),
+                None => false,
+            };
+            let (rows, width, height) = Menus::layout(d, tree, parent)?;
+            if gone || rows.is_empty() {
+                if level == 0 {
+                    return self.close(d);
+                }
+                return self.close_from(d, level);
+            }
+            let popup = &mut self.popups[level];
+            popup.hover = popup.hover.filter(|&h| h < rows.len());
+            popup.rows = rows;
+            popup.width = width;
+            popup.height = height;
+            d.conn
+                .configure_window(
+                    popup.window,
+                    &ConfigureWindowAux::new()
+                        .width(u32::from(width))
+                        .height(u32::from(height)),
+                )
+                .map_err(x11_error)?;
+            let window = popup.window;
+            self.paint(d, tree, window)?;
+        }
+        d.conn.flush().map_err(x11_error)
+    }
+
+    pub fn paint(&self, d: &Display, tree: &MenuTree, window: Window) -> Result<(), Error> {
+        let popup = match self.popups.iter().find(|p| p.window == window) {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let conn = &d.conn;
+        for (i, row) in popup.rows.iter().enumerate() {
+            let entry = match tree.get(row.idx) {
+                Some(e) => e,
+                None => continue,
+            };
+            let highlighted =
+                popup.hover == Some(i) && entry.enabled && entry.kind != EntryKind::Separator;
+            let (background, foreground) = if highlighted {
+                (d.palette.highlight, d.palette.highlight_text)
+            } else if entry.enabled {
+                (d.palette.background, d.palette.text)
+            } else {
+                (d.palette.background, d.palette.disabled)
+            };
+            let y = row.y as i16;
+            conn.change_gc(d.gc, &ChangeGCAux::new().foreground(background))
+                .map_err(x11_error)?;
+            conn.poly_fill_rectangle(
+                window,
+                d.gc,
+                &[Rectangle {
+                    x: 0,
+                    y,
+                    width: popup.width,
+                    height: row.height,
+                }],
+            )
+            .map_err(x11_error)?;
+            conn.change_gc(
+                d.gc,
+                &ChangeGCAux::new()
+                    .foreground(foreground)
+                    .background(background),
+            )
+            .map_err(x11_error)?;
+            if entry.kind == EntryKind::Separator {
+                let middle = y + (row.height / 2) as i16;
+                conn.change_gc(d.gc, &ChangeGCAux::new().foreground(d.palette.border))
+                    .map_err(x11_error)?;
+                conn.poly_line(
+                    CoordMode::ORIGIN,
+                    window,
+                    d.gc,
+                    &[
+                        Point { x: 4, y: middle },
+                        Point {
+                            x: popup.width as i16 - 5,
+                            y: middle,
+                        },
+                    ],
+                )
+                .map_err(x11_error)?;
+                continue;
+            }
+            let baseline = y + (ROW_PADDING as i16) + d.font.ascent;
+            conn.image_text16(
+                window,
+                d.gc,
+                MARK_WIDTH as i16,
+                baseline,
+                &text16(&entry.label),
+            )
+            .map_err(x11_error)?;
+
+            // Marks are drawn in a square the height of the text.
+            let size = d.font.ascent.max(8) - 2;
+            let mark_x = (MARK_WIDTH as i16 - size) / 2;
+            let mark_y = y + (row.height as i16 - size) / 2;
+            match entry.kind {
+                EntryKind::Check => {
+                    conn.poly_rectangle(
+                        window,
+                        d.gc,
+                        &[Rectangle {
+                            x: mark_x,
+                            y: mark_y,
+                            width: size as u16,
+                            height: size as u16,
+                        }],
+                    )
+                    .map_err(x11_error)?;
+                    if entry.checked {
+                        let points = [
+                            Point {
+                                x: mark_x + 2,
+                                y: mark_y + size / 2,
+                            },
+                            Point {
+                                x: mark_x + size / 2 - 1,
+                                y: mark_y + size - 3,
+                            },
+                            Point {
+                                x: mark_x + size - 2,
+                                y: mark_y + 2,
+                            },
+                        ];
+                        conn.poly_line(CoordMode::ORIGIN, window, d.gc, &points)
+                            .map_err(x11_error)?;
+                    }
+                }
+                EntryKind::Radio => {
+                    let circle = XArc {
+                        x: mark_x,
+                        y: mark_y,
+                        width: size as u16,
+                        height: size as u16,
+                        angle1: 0,
+                        angle2: 360 * 64,
+                    };
+                    conn.poly_arc(window, d.gc, &[circle]).map_err(x11_error)?;
+                    if entry.checked {
+                        let dot = XArc {
+                            x: mark_x + 3,
+                            y: mark_y + 3,
+                            width: (size - 6).max(2) as u16,
+                            height: (size - 6).max(2) as u16,
+                            ..circle
+                        };
+                        conn.poly_fill_arc(window, d.gc, &[dot])
+                            .map_err(x11_error)?;
+                    }
+                }
+                EntryKind::Submenu => {
+                    let x = popup.width as i16 - ARROW_WIDTH as i16 / 2 - 2;
+                    let middle = y + row.height as i16 / 2;
+                    let half = (size / 2).max(3);
+                    let points = [
+                        Point {
+                            x,
+                            y: middle - half,
+                        },
+                        Point {
+                            x: x + half,
+                            y: middle,
+                        },
+                        Point {
+                            x,
+                            y: middle + half,
+                        },
+                    ];
+                    conn.fill_poly(window, d.gc, PolyShape::CONVEX, CoordMode::ORIGIN, &points)
+                        .map_err(x11_error)?;
+                }
+                EntryKind::Standard | EntryKind::Separator => {}
+            }
+        }
+        conn.flush().map_err(x11_error)
+    }
+}
@@ -0,0 +1,1514 @@
+// XEmbed system tray backend, enabled with the "xembed" feature. Docks an icon
+// window into the tray that owns the _NET_SYSTEM_TRAY_Sn selection, for X11
+// panels without StatusNotifierItem support.
+//
+// Everything the application can change lives in State, shared with a loop
+// thread that owns the X11 windows. Changes are made from the calling thread,
+// followed by a client message that wakes the loop up to repaint. The loop
+// also docks again when a new tray takes the selection, and steps animations
+// while it waits for events.
+
+mod menu;
+
+use self::menu::{text16, Entry, EntryKind, MenuTree, Menus};
+use super::{notifications::Notifier, Backend, BackendHandle};
+use crate::{
+    icon::IconImage, ApplicationBuilder, Error, Event, EventSender, Icon, Notification,
+    ScrollOrientation, Status,
+};
+use std::{
+    env, fmt, fs, io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        xproto::{
+            Atom, AtomEnum, BackPixmap, ChangeGCAux, ChangeWindowAttributesAux, ClientMessageEvent,
+            Colormap, ColormapAlloc, ConnectionExt, CreateGCAux, CreateWindowAux, EventMask,
+            Gcontext, ImageFormat, ImageOrder, PropMode, Timestamp, VisualClass, Visualtype,
+            Window as XWindow, WindowClass,
+        },
+        Event as XEvent,
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+    CURRENT_TIME, NONE,
+};
+
+const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+const XEMBED_MAPPED: u32 = 1;
+// Trays resize the icon once docked.
+const DEFAULT_SIZE: u16 = 24;
+// Milliseconds between the presses of a double click.
+const DOUBLE_CLICK_TIME: u32 = 400;
+// Tried in order, the first has the widest coverage.
+const FONTS: &[&str] = &[
+    "-misc-fixed-medium-r-normal--13-*-*-*-*-*-iso10646-1",
+    "fixed",
+];
+
+x11rb::atom_manager! {
+    pub(crate) Atoms: AtomsCookie {
+        MANAGER,
+        UTF8_STRING,
+        _NET_WM_NAME,
+        _NET_WM_WINDOW_TYPE,
+        _NET_WM_WINDOW_TYPE_POPUP_MENU,
+        _NET_WM_WINDOW_TYPE_TOOLTIP,
+        _NET_SYSTEM_TRAY_OPCODE,
+        _NET_SYSTEM_TRAY_VISUAL,
+        _XEMBED_INFO,
+        _SYSTRAY_RS_WAKE,
+    }
+}
+
+pub(crate) fn x11_error<E: fmt::Display>(e: E) -> Error {
+    Error::OsError(format!("X11 error: {}", e))
+}
+
+fn selection_atom(conn: &RustConnection, screen: usize) -> Result<Atom, Error> {
+    let name = format!("_NET_SYSTEM_TRAY_S{}", screen);
+    Ok(conn
+        .intern_atom(false, name.as_bytes())
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?
+        .atom)
+}
+
+fn tray_owner(conn: &RustConnection, selection: Atom) -> Result<XWindow, Error> {
+    Ok(conn
+        .get_selection_owner(selection)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?
+        .owner)
+}
+
+pub(crate) fn is_tray_available() -> bool {
+    let (conn, screen) = match x11rb::connect(None) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    selection_atom(&conn, screen)
+        .and_then(|selection| tray_owner(&conn, selection))
+        .map(|owner| owner != NONE)
+        .unwrap_or(false)
+}
+
+// Scales one channel to the bits of `mask`.
+fn pack_channel(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let bits = (mask >> shift).count_ones().min(8);
+    (u32::from(value) >> (8 - bits)) << shift
+}
+
+fn unpack_channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let bits = (mask >> shift).count_ones().min(8);
+    (((pixel & mask) >> shift) << (8 - bits)) as u8
+}
+
+fn pack(visual: &Visualtype, r: u8, g: u8, b: u8) -> u32 {
+    pack_channel(r, visual.red_mask)
+        | pack_channel(g, visual.green_mask)
+        | pack_channel(b, visual.blue_mask)
+}
+
+// Resizes RGBA pixels, averaging the source pixels each target pixel covers
+// weighted by their alpha.
+fn scale(image: &IconImage, width: u32, height: u32) -> Vec<u8> {
+    if image.width == width && image.height == height {
+        return image.rgba.clone();
+    }
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        let y0 = y * image.height / height;
+        let y1 = ((y + 1) * image.height / height).max(y0 + 1);
+        for x in 0..width {
+            let x0 = x * image.width / width;
+            let x1 = ((x + 1) * image.width / width).max(x0 + 1);
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let p = (sy * image.width + sx) as usize * 4;
+                    let pixel = &image.rgba[p..p + 4];
+                    let a = u32::from(pixel[3]);
+                    for (sum, &c) in sum.iter_mut().zip(&pixel[..3]) {
+                        *sum += u32::from(c) * a;
+                    }
+                    sum[3] += a;
+                    count += 1;
+                }
+            }
+            match sum[3] {
+                0 => rgba.extend_from_slice(&[0, 0, 0, 0]),
+                alpha => rgba.extend_from_slice(&[
+                    (sum[0] / alpha) as u8,
+                    (sum[1] / alpha) as u8,
+                    (sum[2] / alpha) as u8,
+                    (alpha / count) as u8,
+                ]),
+            }
+        }
+    }
+    rgba
+}
+
+// Looks the name up the way trays without icon themes usually do: in the
+// icon theme path, the hicolor theme and the pixmaps directory.
+fn find_icon(name: &str, theme_path: &str) -> Option<PathBuf> {
+    if Path::new(name).is_absolute() {
+        return Some(PathBuf::from(name));
+    }
+    let extensions: &[&str] = if cfg!(feature = "svg") {
+        &["png", "svg"]
+    } else {
+        &["png"]
+    };
+    let mut dirs = vec![];
+    if !theme_path.is_empty() {
+        dirs.push(PathBuf::from(theme_path));
+    }
+    let mut data_dirs = vec![];
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) => data_dirs.push(PathBuf::from(dir)),
+        None => {
+            if let Some(home) = env::var_os("HOME") {
+                data_dirs.push(Path::new(&home).join(".local/share"));
+            }
+        }
+    }
+    let system_dirs =
+        env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".to_owned());
+    data_dirs.extend(
+        system_dirs
+            .split(':')
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from),
+    );
+    for data_dir in &data_dirs {
+        let hicolor = data_dir.join("icons/hicolor");
+        for size in &[
+            "48x48", "32x32", "64x64", "24x24", "22x22", "16x16", "128x128", "256x256", "scalable",
+        ] {
+            dirs.push(hicolor.join(size).join("apps"));
+            dirs.push(hicolor.join(size).join("status"));
+        }
+    }
+    dirs.extend(data_dirs.iter().map(|d| d.join("pixmaps")));
+    dirs.iter()
+        .flat_map(|dir| {
+            extensions
+                .iter()
+                .map(move |ext| dir.join(format!("{}.{}", name, ext)))
+        })
+        .find(|path| path.is_file())
+}
+
+pub(crate) struct Font {
+    id: u32,
+    ascent: i16,
+    descent: i16,
+}
+
+impl Font {
+    fn height(&self) -> u16 {
+        (self.ascent + self.descent).max(1) as u16
+    }
+}
+
+pub(crate) struct Palette {
+    background: u32,
+    border: u32,
+    text: u32,
+    disabled: u32,
+    highlight: u32,
+    highlight_text: u32,
+}
+
+impl Palette {
+    fn new(visual: &Visualtype, black: u32, white: u32) -> Palette {
+        let color = |rgb: u32, fallback: u32| {
+            if visual.class == VisualClass::TRUE_COLOR || visual.class == VisualClass::DIRECT_COLOR
+            {
+                pack(visual, (rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+            } else {
+                fallback
+            }
+        };
+        Palette {
+            background: color(0xf0f0f0, white),
+            border: color(0x909090, black),
+            text: color(0x000000, black),
+            disabled: color(0x909090, black),
+            highlight: color(0x3875d7, black),
+            highlight_text: color(0xffffff, white),
+        }
+    }
+}
+
+// The X11 connection, and what menus and tooltips are drawn with.
+pub(crate) struct Display {
+    conn: Arc<RustConnection>,
+    screen: usize,
+    root: XWindow,
+    width: u16,
+    height: u16,
+    root_depth: u8,
+    root_visual: Visualtype,
+    atoms: Atoms,
+    font: Font,
+    gc: Gcontext,
+    palette: Palette,
+}
+
+impl Display {
+    fn new(conn: Arc<RustConnection>, screen: usize) -> Result<Display, Error> {
+        let atoms = Atoms::new(&*conn)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        let info = conn.setup().roots[screen].clone();
+        let (root_depth, root_visual) = Display::find_visual(&conn, screen, info.root_visual)
+            .ok_or_else(|| Error::OsError("Cannot find the X11 root visual".to_owned()))?;
+        let font = Display::open_font(&conn)?;
+        let gc = conn.generate_id().map_err(x11_error)?;
+        conn.create_gc(
+            gc,
+            info.root,
+            &CreateGCAux::new().font(font.id).graphics_exposures(0),
+        )
+        .map_err(x11_error)?;
+        Ok(Display {
+            palette: Palette::new(&root_visual, info.black_pixel, info.white_pixel),
+            conn,
+            screen,
+            root: info.root,
+            width: info.width_in_pixels,
+            height: info.height_in_pixels,
+            root_depth,
+            root_visual,
+            atoms,
+            font,
+            gc,
+        })
+    }
+
+    fn open_font(conn: &RustConnection) -> Result<Font, Error> {
+        for name in FONTS {
+            let id = conn.generate_id().map_err(x11_error)?;
+            let opened = conn
+                .open_font(id, name.as_bytes())
+                .map_err(x11_error)?
+                .check();
+            if opened.is_err() {
+                continue;
+            }
+            let info = conn
+                .query_font(id)
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?;
+            return Ok(Font {
+                id,
+                ascent: info.font_ascent,
+                descent: info.font_descent,
+            });
+        }
+        Err(Error::OsError("No usable X11 core font".to_owned()))
+    }
+
+    fn find_visual(conn: &RustConnection, screen: usize, id: u32) -> Option<(u8, Visualtype)> {
+        conn.setup().roots[screen]
+            .allowed_depths
+            .iter()
+            .find_map(|depth| {
+                depth
+                    .visuals
+                    .iter()
+                    .find(|v| v.visual_id == id)
+                    .map(|v| (depth.depth, *v))
+            })
+    }
+
+    fn bits_per_pixel(&self, depth: u8) -> Option<u8> {
+        self.conn
+            .setup()
+            .pixmap_formats
+            .iter()
+            .find(|f| f.depth == depth)
+            .map(|f| f.bits_per_pixel)
+    }
+
+    fn lsb_first(&self) -> bool {
+        self.conn.setup().image_byte_order == ImageOrder::LSB_FIRST
+    }
+
+    fn text_width(&self, text: &str) -> Result<u16, Error> {
+        let extents = self
+            .conn
+            .query_text_extents(self.font.id, &text16(text))
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        Ok(extents.overall_width.clamp(0, i32::from(u16::MAX / 2)) as u16)
+    }
+
+    // Override-redirect window on the root window, for menus and tooltips.
+    fn popup(&self, x: i16, y: i16, width: u16, height: u16, kind: Atom) -> Result<XWindow, Error> {
+        let window = self.conn.generate_id().map_err(x11_error)?;
+        let aux = CreateWindowAux::new()
+            .background_pixel(self.palette.background)
+            .border_pixel(self.palette.border)
+            .override_redirect(1)
+            .save_under(1)
+            .event_mask(
+                EventMask::EXPOSURE
+                    | EventMask::BUTTON_PRESS
+                    | EventMask::BUTTON_RELEASE
+                    | EventMask::POINTER_MOTION,
+            );
+        self.conn
+            .create_window(
+                self.root_depth,
+                window,
+                self.root,
+                x,
+                y,
+                width,
+                height,
+                1,
+                WindowClass::INPUT_OUTPUT,
+                self.root_visual.visual_id,
+                &aux,
+            )
+            .map_err(x11_error)?;
+        self.conn
+            .change_property32(
+                PropMode::REPLACE,
+                window,
+                self.atoms._NET_WM_WINDOW_TYPE,
+                AtomEnum::ATOM,
+                &[kind],
+            )
+            .map_err(x11_error)?;
+        self.conn.map_window(window).map_err(x11_error)?;
+        Ok(window)
+    }
+}
+
+struct Animation {
+    frames: Vec<Icon>,
+    frame: usize,
+    interval: Duration,
+    // When the loop shows the next frame.
+    next_frame: Instant,
+}
+
+pub(crate) struct State {
+    icon: Option<Icon>,
+    attention_icon: Option<Icon>,
+    animation: Option<Animation>,
+    status: Status,
+    tooltip: String,
+    theme_path: String,
+    menu: MenuTree,
+    quit: bool,
+}
+
+impl State {
+    fn current_icon(&self) -> Option<&Icon> {
+        if self.status == Status::NeedsAttention && self.attention_icon.is_some() {
+            return self.attention_icon.as_ref();
+        }
+        match self.animation {
+            Some(ref a) => Some(&a.frames[a.frame]),
+            None => self.icon.as_ref(),
+        }
+    }
+}
+
+// The window docked into the tray.
+struct IconWindow {
+    window: XWindow,
+    gc: Gcontext,
+    depth: u8,
+    visual: Visualtype,
+    // Drawn with alpha on a 32 bit visual, instead of over the background
+    // of the tray.
+    argb: bool,
+    colormap: Option<Colormap>,
+    width: u16,
+    height: u16,
+    mapped: bool,
+}
+
+struct Tooltip {
+    window: XWindow,
+    text: String,
+}
+
+// The loop thread's side of the tray.
+struct Tray {
+    display: Display,
+    selection: Atom,
+    wake: XWindow,
+    state: Arc<Mutex<State>>,
+    event_tx: EventSender,
+    id: String,
+    title: String,
+    manager: Option<XWindow>,
+    icon: Option<IconWindow>,
+    menus: Menus,
+    tooltip: Option<Tooltip>,
+    last_press: Option<Timestamp>,
+    // Set when a click closed the menu or finished a double click, so its
+    // release doesn't open the menu again.
+    ignore_release: bool,
+}
+
+impl Tray {
+    // Asks the tray that owns the selection to embed a new icon window.
+    fn dock(&mut self) -> Result<(), Error> {
+        let conn = self.display.conn.clone();
+        let manager = tray_owner(&conn, self.selection)?;
+        if manager == NONE {
+            return Err(Error::OsError(
+                "No XEmbed system tray is running".to_owned(),
+            ));
+        }
+        // Hear about the tray going away.
+        conn.change_window_attributes(
+            manager,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY),
+        )
+        .map_err(x11_error)?;
+        self.destroy_icon()?;
+
+        let (depth, visual) = self
+            .tray_visual(manager)
+            .unwrap_or((self.display.root_depth, self.display.root_visual));
+        if self.display.bits_per_pixel(depth) != Some(32)
+            || (visual.class != VisualClass::TRUE_COLOR
+                && visual.class != VisualClass::DIRECT_COLOR)
+        {
+            return Err(Error::OsError(format!(
+                "Unsupported X11 visual for the tray icon (depth {})",
+                depth
+            )));
+        }
+        let argb = depth == 32;
+        let window = conn.generate_id().map_err(x11_error)?;
+        let mut aux = CreateWindowAux::new().event_mask(
+            EventMask::EXPOSURE
+                | EventMask::STRUCTURE_NOTIFY
+                | EventMask::BUTTON_PRESS
+                | EventMask::BUTTON_RELEASE
+                | EventMask::ENTER_WINDOW
+                | EventMask::LEAVE_WINDOW,
+        );
+        let colormap = if argb {
+            let colormap = conn.generate_id().map_err(x11_error)?;
+            conn.create_colormap(
+                ColormapAlloc::NONE,
+                colormap,
+                self.display.root,
+                visual.visual_id,
+            )
+            .map_err(x11_error)?;
+            aux = aux.colormap(colormap).background_pixel(0).border_pixel(0);
+            Some(colormap)
+        } else {
+            aux = aux.background_pixmap(u32::from(BackPixmap::PARENT_RELATIVE));
+            None
+        };
+        conn.create_window(
+            depth,
+            window,
+            self.display.root,
+            0,
+            0,
+            DEFAULT_SIZE,
+            DEFAULT_SIZE,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            visual.visual_id,
+            &aux,
+        )
+        .map_err(x11_error)?;
+
+        let atoms = &self.display.atoms;
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            AtomEnum::WM_NAME,
+            AtomEnum::STRING,
+            self.title.as_bytes(),
+        )
+        .map_err(x11_error)?;
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            atoms._NET_WM_NAME,
+            atoms.UTF8_STRING,
+            self.title.as_bytes(),
+        )
+        .map_err(x11_error)?;
+        let class = format!("{0}\0{0}\0", self.id);
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            AtomEnum::WM_CLASS,
+            AtomEnum::STRING,
+            class.as_bytes(),
+        )
+        .map_err(x11_error)?;
+        let mapped = self.state.lock().unwrap().status != Status::Passive;
+        let flags = if mapped { XEMBED_MAPPED } else { 0 };
+        conn.change_property32(
+            PropMode::REPLACE,
+            window,
+            atoms._XEMBED_INFO,
+            atoms._XEMBED_INFO,
+            &[0, flags],
+        )
+        .map_err(x11_error)?;
+        let gc = conn.generate_id().map_err(x11_error)?;
+        conn.create_gc(gc, window, &CreateGCAux::new().graphics_exposures(0))
+            .map_err(x11_error)?;
+        self.icon = Some(IconWindow {
+            window,
+            gc,
+            depth,
+            visual,
+            argb,
+            colormap,
+            width: DEFAULT_SIZE,
+            height: DEFAULT_SIZE,
+            mapped,
+        });
+
+        let dock = ClientMessageEvent::new(
+            32,
+            manager,
+            atoms._NET_SYSTEM_TRAY_OPCODE,
+            [CURRENT_TIME, SYSTEM_TRAY_REQUEST_DOCK, window, 0, 0],
+        );
+        conn.send_event(false, manager, EventMask::NO_EVENT, dock)
+            .map_err(x11_error)?;
+        conn.flush().map_err(x11_error)?;
+        self.manager = Some(manager);
+        Ok(())
+    }
+
+    // Trays with a compositor ask for an ARGB visual.
+    fn tray_visual(&self, manager: XWindow) -> Option<(u8, Visualtype)> {
+        let reply = self
+            .display
+            .conn
+            .get_property(
+                false,
+                manager,
+                self.display.atoms._NET_SYSTEM_TRAY_VISUAL,
+                AtomEnum::VISUALID,
+                0,
+                1,
+            )
+            .ok()?
+            .reply()
+            .ok()?;
+        let id = reply.value32()?.next()?;
+        Display::find_visual(&self.display.conn, self.display.screen, id)
+    }
+
+    fn destroy_icon(&mut self) -> Result<(), Error> {
+        let conn = &self.display.conn;
+        if let Some(icon) = self.icon.take() {
+            conn.destroy_window(icon.window).map_err(x11_error)?;
+            conn.free_gc(icon.gc).map_err(x11_error)?;
+            if let Some(colormap) = icon.colormap {
+                conn.free_colormap(colormap).map_err(x11_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Shows the current icon, scaled to fit and centered.
+    fn paint(&self) -> Result<(), Error> {
+        let icon = match self.icon {
+            Some(ref icon) => icon,
+            None => return Ok(()),
+        };
+        let conn = &self.display.conn;
+        let (width, height) = (u32::from(icon.width), u32::from(icon.height));
+        let size = width.min(height);
+        let (pixels, image_width, image_height) = {
+            let state = self.state.lock().unwrap();
+            match state.current_icon() {
+                Some(current) => {
+                    let image = current.best_for_size(size);
+                    let (w, h) = if image.width >= image.height {
+                        (size, (image.height * size / image.width).max(1))
+                    } else {
+                        ((image.width * size / image.height).max(1), size)
+                    };
+                    (scale(image, w, h), w, h)
+                }
+                None => (vec![], 0, 0),
+            }
+        };
+
+        // Without alpha, blend over what the tray shows behind the window.
+        let mut data = if icon.argb {
+            vec![0; width as usize * height as usize * 4]
+        } else {
+            conn.clear_area(false, icon.window, 0, 0, 0, 0)
+                .map_err(x11_error)?;
+            let image = conn
+                .get_image(
+                    ImageFormat::Z_PIXMAP,
+                    icon.window,
+                    0,
+                    0,
+                    icon.width,
+                    icon.height,
+                    !0,
+                )
+                .map_err(x11_error)?
+                .reply();
+            match image {
+                Ok(image) if image.data.len() == width as usize * height as usize * 4 => image.data,
+                // Not viewable yet, it is painted on the next Expose.
+                _ => return conn.flush().map(|_| ()).map_err(x11_error),
+            }
+        };
+        let lsb_first = self.display.lsb_first();
+        let (left, top) = ((width - image_width) / 2, (height - image_height) / 2);
+        for y in 0..image_height {
+            for x in 0..image_width {
+                let s = (y * image_width + x) as usize * 4;
+                let (r, g, b, a) = (
+                    pixels[s],
+                    pixels[s + 1],
+                    pixels[s + 2],
+                    u32::from(pixels[s + 3]),
+                );
+                if a == 0 {
+                    continue;
+                }
+                let d = ((top + y) * width + left + x) as usize * 4;
+                let bytes = &mut data[d..d + 4];
+                let pixel = if icon.argb {
+                    let premultiply = |c: u8| (u32::from(c) * a / 255) as u8;
+                    pack(&icon.visual, premultiply(r), premultiply(g), premultiply(b)) | (a << 24)
+                } else {
+                    let mut old = [0; 4];
+                    old.copy_from_slice(bytes);
+                    let old = if lsb_first {
+                        u32::from_le_bytes(old)
+                    } else {
+                        u32::from_be_bytes(old)
+                    };
+                    let blend = |c: u8, mask: u32| {
+                        let under = u32::from(unpack_channel(old, mask));
+                        ((u32::from(c) * a + under * (255 - a)) / 255) as u8
+                    };
+                    pack(
+                        &icon.visual,
+                        blend(r, icon.visual.red_mask),
+                        blend(g, icon.visual.green_mask),
+                        blend(b, icon.visual.blue_mask),
+                    )
+                };
+                let pixel = if lsb_first {
+                    pixel.to_le_bytes()
+                } else {
+                    pixel.to_be_bytes()
+                };
+                bytes.copy_from_slice(&pixel);
+            }
+        }
+        conn.put_image(
+            ImageFormat::Z_PIXMAP,
+            icon.window,
+            icon.gc,
+            icon.width,
+            icon.height,
+            0,
+            0,
+            0,
+            icon.depth,
+            &data,
+        )
+        .map_err(x11_error)?;
+        conn.flush().map_err(x11_error)
+    }
+
+    // Passive icons are hidden, through _XEMBED_INFO for the tray and by
+    // unmapping the window for trays that don't follow it.
+    fn update_mapped(&mut self) -> Result<(), Error> {
+        let mapped = self.state.lock().unwrap().status != Status::Passive;
+        let conn = &self.display.conn;
+        let icon = match self.icon {
+            Some(ref mut icon) if icon.mapped != mapped => icon,
+            _ => return Ok(()),
+        };
+        icon.mapped = mapped;
+        let flags = if mapped { XEMBED_MAPPED } else { 0 };
+        conn.change_property32(
+            PropMode::REPLACE,
+            icon.window,
+            self.display.atoms._XEMBED_INFO,
+            self.display.atoms._XEMBED_INFO,
+            &[0, flags],
+        )
+        .map_err(x11_error)?;
+        if self.manager.is_some() {
+            if mapped {
+                conn.map_window(icon.window).map_err(x11_error)?;
+            } else {
+                conn.unmap_window(icon.window).map_err(x11_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn over_icon(&self, root_x: i16, root_y: i16) -> bool {
+        let icon = match self.icon {
+            Some(ref icon) => icon,
+            None => return false,
+        };
+        let position = self
+            .display
+            .conn
+            .translate_coordinates(self.display.root, icon.window, root_x, root_y)
+            .ok()
+            .and_then(|c| c.reply().ok());
+        match position {
+            Some(p) => {
+                p.dst_x >= 0
+                    && p.dst_y >= 0
+                    && (p.dst_x as u16) < icon.width
+                    && (p.dst_y as u16) < icon.height
+            }
+            None => false,
+        }
+    }
+
+    fn open_menu(&mut self, x: i16, y: i16) -> Result<bool, Error> {
+        self.hide_tooltip()?;
+        let opened = {
+            let state = self.state.lock().unwrap();
+            self.menus.open(&self.display, &state.menu, x, y)?
+        };
+        Ok(!opened || self.event_tx.send(Event::MenuOpened))
+    }
+
+    fn close_menu(&mut self) -> Result<bool, Error> {
+        if !self.menus.is_open() {
+            return Ok(true);
+        }
+        self.menus.close(&self.display)?;
+        Ok(self.event_tx.send(Event::MenuClosed))
+    }
+
+    fn show_tooltip(&mut self) -> Result<(), Error> {
+        self.hide_tooltip()?;
+        let text = self.state.lock().unwrap().tooltip.clone();
+        let icon = match self.icon {
+            Some(ref icon) if !text.is_empty() && !self.menus.is_open() => icon,
+            _ => return Ok(()),
+        };
+        let d = &self.display;
+        let position = d
+            .conn
+            .translate_coordinates(icon.window, d.root, 0, 0)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+        let width = d.text_width(&text)? + 8;
+        let height = d.font.height() + 6;
+        let x = i32::from(position.dst_x).min(i32::from(d.width) - i32::from(width) - 2);
+        let mut y = i32::from(position.dst_y) + i32::from(icon.height) + 4;
+        if y + i32::from(height) > i32::from(d.height) {
+            y = i32::from(position.dst_y) - i32::from(height) - 6;
+        }
+        let window = d.popup(
+            x.max(0) as i16,
+            y.max(0) as i16,
+            width,
+            height,
+            d.atoms._NET_WM_WINDOW_TYPE_TOOLTIP,
+        )?;
+        d.conn.flush().map_err(x11_error)?;
+        self.tooltip = Some(Tooltip { window, text });
+        Ok(())
+    }
+
+    fn paint_tooltip(&self) -> Result<(), Error> {
+        let tooltip = match self.tooltip {
+            Some(ref t) => t,
+            None => return Ok(()),
+        };
+        let d = &self.display;
+        d.conn
+            .change_gc(
+                d.gc,
+                &ChangeGCAux::new()
+                    .foreground(d.palette.text)
+                    .background(d.palette.background),
+            )
+            .map_err(x11_error)?;
+        d.conn
+            .image_text16(
+                tooltip.window,
+                d.gc,
+                4,
+                3 + d.font.ascent,
+                &text16(&tooltip.text),
+            )
+            .map_err(x11_error)?;
+        d.conn.flush().map_err(x11_error)
+    }
+
+    fn hide_tooltip(&mut self) -> Result<(), Error> {
+        if let Some(tooltip) = self.tooltip.take() {
+            self.display
+                .conn
+                .destroy_window(tooltip.window)
+                .map_err(x11_error)?;
+            self.display.conn.flush().map_err(x11_error)?;
+        }
+        Ok(())
+    }
+
+    // Catches up with changes made through the handle.
+    fn refresh(&mut self) -> Result<bool, Error> {
+        if self.state.lock().unwrap().quit {
+            return Ok(false);
+        }
+        self.update_mapped()?;
+        self.paint()?;
+        if self.menus.is_open() {
+            {
+                let state = self.state.lock().unwrap();
+                self.menus.refresh(&self.display, &state.menu)?;
+            }
+            if !self.menus.is_open() && !self.event_tx.send(Event::MenuClosed) {
+                return Ok(false);
+            }
+        }
+        let tooltip = self.state.lock().unwrap().tooltip.clone();
+        if self
+            .tooltip
+            .as_ref()
+            .map(|t| t.text != tooltip)
+            .unwrap_or(false)
+        {
+            self.show_tooltip()?;
+        }
+        Ok(true)
+    }
+
+    fn is_icon(&self, window: XWindow) -> bool {
+        self.icon
+            .as_ref()
+            .map(|i| i.window == window)
+            .unwrap_or(false)
+    }
+
+    // Returns false once the loop should stop.
+    fn handle_event(&mut self, event: XEvent) -> Result<bool, Error> {
+        let atoms = self.display.atoms;
+        match event {
+            XEvent::ClientMessage(e)
+                if e.window == self.wake && e.type_ == atoms._SYSTRAY_RS_WAKE =>
+            {
+                return self.refresh();
+            }
+            XEvent::ClientMessage(e)
+                if e.type_ == atoms.MANAGER && e.data.as_data32()[1] == self.selection =>
+            {
+                // A tray started, or took over from the old one.
+                self.close_menu()?;
+                self.hide_tooltip()?;
+                match self.dock() {
+                    Ok(()) => {
+                        return Ok(self
+                            .event_tx
+                            .send(Event::TrayHostChanged { available: true }))
+                    }
+                    Err(e) => log::debug!("Cannot dock into the new XEmbed tray: {}", e),
+                }
+            }
+            XEvent::DestroyNotify(e) if Some(e.window) == self.manager => {
+                self.manager = None;
+                self.close_menu()?;
+                self.hide_tooltip()?;
+                // Trays that put us in their save set leave the window on the
+                // root window.
+                if let Some(ref icon) = self.icon {
+                    self.display
+                        .conn
+                        .unmap_window(icon.window)
+                        .map_err(x11_error)?;
+                    self.display.conn.flush().map_err(x11_error)?;
+                }
+                return Ok(self
+                    .event_tx
+                    .send(Event::TrayHostChanged { available: false }));
+            }
+            XEvent::DestroyNotify(e) if self.is_icon(e.window) => {
+                // Destroyed along with the tray, freeing the rest.
+                if let Some(icon) = self.icon.take() {
+                    self.display.conn.free_gc(icon.gc).map_err(x11_error)?;
+                    if let Some(colormap) = icon.colormap {
+                        self.display
+                            .conn
+                            .free_colormap(colormap)
+                            .map_err(x11_error)?;
+                    }
+                }
+            }
+            XEvent::ConfigureNotify(e) if self.is_icon(e.window) => {
+                if let Some(ref mut icon) = self.icon {
+                    if (icon.width, icon.height) == (e.width, e.height) {
+                        return Ok(true);
+                    }
+                    icon.width = e.width.max(1);
+                    icon.height = e.height.max(1);
+                }
+                self.paint()?;
+            }
+            XEvent::Expose(e) if e.count == 0 => {
+                if self.is_icon(e.window) {
+                    self.paint()?;
+                } else if self.menus.owns(e.window) {
+                    let state = self.state.lock().unwrap();
+                    self.menus.paint(&self.display, &state.menu, e.window)?;
+                } else if self.tooltip.as_ref().map(|t| t.window) == Some(e.window) {
+                    self.paint_tooltip()?;
+                }
+            }
+            XEvent::MotionNotify(e) if self.menus.is_open() => {
+                let state = self.state.lock().unwrap();
+                self.menus
+                    .motion(&self.display, &state.menu, e.root_x, e.root_y)?;
+            }
+            XEvent::KeyPress(_) if self.menus.is_open() => {
+                // Escape, or anything else typed while the menu is up.
+                return self.close_menu();
+            }
+            XEvent::ButtonPress(e) => {
+                if self.menus.is_open() && self.menus.press(e.root_x, e.root_y) {
+                    return Ok(true);
+                }
+                let over_icon = self.over_icon(e.root_x, e.root_y);
+                if self.menus.is_open() {
+                    self.ignore_release = over_icon;
+                    if !self.close_menu()? {
+                        return Ok(false);
+                    }
+                }
+                if !over_icon {
+                    return Ok(true);
+                }
+                self.hide_tooltip()?;
+                let event = match e.detail {
+                    1 => {
+                        let double = self
+                            .last_press
+                            .map(|t| e.time.wrapping_sub(t) <= DOUBLE_CLICK_TIME)
+                            .unwrap_or(false);
+                        if !double {
+                            self.last_press = Some(e.time);
+                            return Ok(true);
+                        }
+                        self.last_press = None;
+                        self.ignore_release = true;
+                        Event::DoubleClick
+                    }
+                    4..=7 => {
+                        let delta = if e.detail % 2 == 0 { -1 } else { 1 };
+                        let orientation = if e.detail < 6 {
+                            ScrollOrientation::Vertical
+                        } else {
+                            ScrollOrientation::Horizontal
+                        };
+                        Event::Scroll { delta, orientation }
+                    }
+                    _ => return Ok(true),
+                };
+                return Ok(self.event_tx.send(event));
+            }
+            XEvent::ButtonRelease(e) => {
+                if self.menus.is_open() {
+                    let activated = {
+                        let state = self.state.lock().unwrap();
+                        self.menus.release(&state.menu, e.root_x, e.root_y)
+                    };
+                    if let Some(index) = activated {
+                        return Ok(self.close_menu()?
+                            && self.event_tx.send(Event::MenuItemActivated { index }));
+                    }
+                    return Ok(true);
+                }
+                if std::mem::replace(&mut self.ignore_release, false) || !self.is_icon(e.event) {
+                    return Ok(true);
+                }
+                // Like on Windows, both buttons show the menu.
+                match e.detail {
+                    1 => {
+                        return Ok(self.event_tx.send(Event::LeftClick)
+                            && self.open_menu(e.root_x, e.root_y)?);
+                    }
+                    2 => return Ok(self.event_tx.send(Event::MiddleClick)),
+                    3 => return self.open_menu(e.root_x, e.root_y),
+                    _ => {}
+                }
+            }
+            XEvent::EnterNotify(e) if self.is_icon(e.event) => self.show_tooltip()?,
+            XEvent::LeaveNotify(e) if self.is_icon(e.event) => self.hide_tooltip()?,
+            XEvent::Error(e) => log::debug!("X11 error in the tray loop: {:?}", e),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    // Shows the next animation frame if it is due. Returns how long until
+    // the one after, if there is an animation.
+    fn step_animation(&mut self) -> Result<Option<Duration>, Error> {
+        let now = Instant::now();
+        let (due, next_frame) = {
+            let mut state = self.state.lock().unwrap();
+            let animation = match state.animation {
+                Some(ref mut a) => a,
+                None => return Ok(None),
+            };
+            let due = animation.next_frame <= now;
+            if due {
+                animation.frame = (animation.frame + 1) % animation.frames.len();
+                // Frames missed while the loop was busy are skipped.
+                animation.next_frame =
+                    (animation.next_frame + animation.interval).max(now + animation.interval / 2);
+            }
+            (due, animation.next_frame)
+        };
+        if due {
+            self.paint()?;
+        }
+        Ok(Some(next_frame - now))
+    }
+
+    // Waits for the next event, animating the icon in the meantime. The loop
+    // is the only user of its connection, so nothing else reads events off
+    // the socket while it waits.
+    fn next_event(&mut self) -> Result<XEvent, Error> {
+        let conn = self.display.conn.clone();
+        loop {
+            if let Some(event) = conn.poll_for_event().map_err(x11_error)? {
+                return Ok(event);
+            }
+            let timeout = self.step_animation()?;
+            conn.flush().map_err(x11_error)?;
+            // Rounded up, so the frame is due once poll returns.
+            let timeout = timeout
+                .map(|t| t.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32)
+                .unwrap_or(-1);
+            let mut fd = libc::pollfd {
+                fd: conn.stream().as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut fd, 1, timeout) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(x11_error(err));
+                }
+            }
+        }
+    }
+
+    fn run(mut self) {
+        loop {
+            let event = match self.next_event() {
+                Ok(event) => event,
+                Err(e) => {
+                    log::debug!("Lost the X11 connection: {}", e);
+                    break;
+                }
+            };
+            match self.handle_event(event) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => log::debug!("Error handling X11 event: {}", e),
+            }
+        }
+        // Handles report the backend gone from here on.
+        self.state.lock().unwrap().quit = true;
+        self.menus.close(&self.display).ok();
+        self.hide_tooltip().ok();
+        self.destroy_icon().ok();
+        self.display.conn.destroy_window(self.wake).ok();
+        self.display.conn.flush().ok();
+        self.event_tx.send(Event::Shutdown);
+    }
+}
+
+pub struct Window {
+    handle: Handle,
+    notifier: Notifier,
+}
+
+impl Window {
+    pub fn new(event_tx: EventSender, config: ApplicationBuilder) -> Result<Window, Error> {
+        let (conn, screen) = x11rb::connect(None).map_err(x11_error)?;
+        let conn = Arc::new(conn);
+        let display = Display::new(conn.clone(), screen)?;
+        let selection = selection_atom(&conn, screen)?;
+
+        // Handles wake the loop up with messages to this window.
+        let wake = conn.generate_id().map_err(x11_error)?;
+        conn.create_window(
+            0,
+            wake,
+            display.root,
+            -1,
+            -1,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            &CreateWindowAux::new(),
+        )
+        .map_err(x11_error)?;
+        // New trays announce themselves on the root window.
+        conn.change_window_attributes(
+            display.root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY),
+        )
+        .map_err(x11_error)?;
+
+        let id = config.id;
+        let title = config.title.unwrap_or_else(|| id.clone());
+        let notifier = Notifier::new(&title, event_tx.clone());
+        let state = Arc::new(Mutex::new(State {
+            icon: config.icon,
+            attention_icon: None,
+            animation: None,
+            status: Status::Active,
+            tooltip: title.clone(),
+            theme_path: String::new(),
+            menu: MenuTree::default(),
+            quit: false,
+        }));
+        let wake_atom = display.atoms._SYSTRAY_RS_WAKE;
+        let mut tray = Tray {
+            display,
+            selection,
+            wake,
+            state: state.clone(),
+            event_tx,
+            id,
+            title,
+            manager: None,
+            icon: None,
+            menus: Menus::default(),
+            tooltip: None,
+            last_press: None,
+            ignore_release: false,
+        };
+        tray.dock()?;
+        // Handles get a connection of their own, so they never read events
+        // meant for the loop.
+        let (handle_conn, _) = x11rb::connect(None).map_err(x11_error)?;
+        let handle = Handle {
+            conn: Arc::new(handle_conn),
+            wake,
+            wake_atom,
+            state,
+        };
+        if let Some(name) = config.icon_name {
+            handle.set_icon_from_name(&name)?;
+        }
+        thread::spawn(move || tray.run());
+        Ok(Window { handle, notifier })
+    }
+
+    // Adds the entry and repaints any open menu.
+    fn add_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        entry: Entry,
+    ) -> Result<(), Error> {
+        self.handle.update(|state| {
+            if state.menu.insert(item_idx, parent, position, entry) {
+                Ok(())
+            } else {
+                Err(Error::InvalidMenuItem(parent.unwrap_or(item_idx)))
+            }
+        })
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+}
+
+impl Backend for Window {
+    fn add_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        let entry = Entry::new(EntryKind::Standard, item_name);
+        self.add_entry(item_idx, parent, position, entry)
+    }
+
+    fn add_menu_separator(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+    ) -> Result<(), Error> {
+        let entry = Entry::new(EntryKind::Separator, "");
+        self.add_entry(item_idx, parent, position, entry)
+    }
+
+    fn add_submenu(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<(), Error> {
+        let entry = Entry::new(EntryKind::Submenu, item_name);
+        self.add_entry(item_idx, parent, position, entry)
+    }
+
+    fn add_check_menu_entry(
+        &self,
+        item_idx: u32,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+        checked: bool,
+        radio: bool,
+    ) -> Result<(), Error> {
+        let kind = if radio {
+            EntryKind::Radio
+        } else {
+            EntryKind::Check
+        };
+        let mut entry = Entry::new(kind, item_name);
+        entry.checked = checked;
+        self.add_entry(item_idx, parent, position, entry)
+    }
+
+    fn set_menu_entry_checked(&self, item_idx: u32, checked: bool) -> Result<(), Error> {
+        self.handle.update_entry(item_idx, |e| e.checked = checked)
+    }
+
+    fn remove_menu_entry(&self, item_idx: u32) -> Result<(), Error> {
+        self.handle.update(|state| {
+            state.menu.remove(item_idx);
+            Ok(())
+        })
+    }
+
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.handle.set_menu_entry_enabled(item_idx, enabled)
+    }
+
+    fn set_menu_entry_visible(&self, item_idx: u32, visible: bool) -> Result<(), Error> {
+        self.handle.update_entry(item_idx, |e| e.visible = visible)
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.handle.set_menu_entry_label(item_idx, item_name)
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        self.handle.set_icon_from_file(file)
+    }
+
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.handle.set_icon_from_name(name)
+    }
+
+    fn set_icon_theme_path(&self, path: &str) -> Result<(), Error> {
+        self.handle.state.lock().unwrap().theme_path = path.to_owned();
+        Ok(())
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.handle.set_icon(icon)
+    }
+
+    fn shutdown(&self) -> Result<(), Error> {
+        self.handle.stop_animation()
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.handle.set_tooltip(tooltip)
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<u32, Error> {
+        self.notifier.notify(notification)
+    }
+
+    fn quit(&mut self) {
+        self.handle.quit()
+    }
+
+    fn handle(&self) -> Arc<dyn BackendHandle> {
+        Arc::new(Window::handle(self))
+    }
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    conn: Arc<RustConnection>,
+    wake: XWindow,
+    wake_atom: Atom,
+    state: Arc<Mutex<State>>,
+}
+
+impl Handle {
+    fn wake(&self) -> Result<(), Error> {
+        let message = ClientMessageEvent::new(32, self.wake, self.wake_atom, [0u32; 5]);
+        self.conn
+            .send_event(false, self.wake, EventMask::NO_EVENT, message)
+            .map_err(x11_error)?;
+        self.conn.flush().map_err(x11_error)
+    }
+
+    // Changes the state and has the loop show the result.
+    fn update<F: FnOnce(&mut State) -> Result<(), Error>>(&self, f: F) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.quit {
+            return Err(Error::BackendGone);
+        }
+        f(&mut state)?;
+        drop(state);
+        self.wake()
+    }
+
+    fn update_entry<F: FnOnce(&mut Entry)>(&self, item_idx: u32, f: F) -> Result<(), Error> {
+        self.update(|state| match state.menu.get_mut(item_idx) {
+            Some(entry) => {
+                f(entry);
+                Ok(())
+            }
+            None => Err(Error::InvalidMenuItem(item_idx)),
+        })
+    }
+}
+
+impl BackendHandle for Handle {
+    fn set_menu_entry_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.update_entry(item_idx, |e| e.enabled = enabled)
+    }
+
+    fn set_menu_entry_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.update_entry(item_idx, |e| e.label = item_name.to_owned())
+    }
+
+    fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        let data = fs::read(file)
+            .map_err(|e| Error::OsError(format!("Error reading icon file: {}", e)))?;
+        self.set_icon(&Icon::from_bytes(&data)?)
+    }
+
+    fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        let theme_path = self.state.lock().unwrap().theme_path.clone();
+        match find_icon(name, &theme_path) {
+            Some(path) => self.set_icon_from_file(&path.to_string_lossy()),
+            None => Err(Error::InvalidIcon(format!("No icon named {} found", name))),
+        }
+    }
+
+    fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.update(|state| {
+            state.animation = None;
+            state.icon = Some(icon.clone());
+            Ok(())
+        })
+    }
+
+    fn set_animated_icon(&self, frames: &[Icon], frame_duration: Duration) -> Result<(), Error> {
+        // The loop takes it from here, waking up for every frame.
+        let interval = frame_duration.max(Duration::from_millis(1));
+        self.update(|state| {
+            state.animation = Some(Animation {
+                frames: frames.to_vec(),
+                frame: 0,
+                interval,
+                next_frame: Instant::now() + interval,
+            });
+            Ok(())
+        })
+    }
+
+    fn stop_animation(&self) -> Result<(), Error> {
+        // The current frame stays as the icon.
+        self.update(|state| {
+            if let Some(a) = state.animation.take() {
+                state.icon = a.frames.into_iter().nth(a.frame);
+            }
+            Ok(())
+        })
+    }
+
+    fn set_label(&self, _label: &str, _guide: &str) -> Result<(), Error> {
+        // XEmbed trays only show the icon.
+        Err(Error::Unsupported { feature: "labels" })
+    }
+
+    fn set_status(&self, status: Status) -> Result<(), Error> {
+        self.update(|state| {
+            state.status = status;
+            Ok(())
+        })
+    }
+
+    fn set_attention_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.update(|state| {
+            state.attention_icon = Some(icon.clone());
+            Ok(())
+        })
+    }
+
+    fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.update(|state| {
+            state.tooltip = tooltip.to_owned();
+            Ok(())
+        })
+    }
+
+    fn quit(&self) {
+        self.update(|state| {
+            state.quit = true;
+            Ok(())
+        })
+        .ok();
+    }
+}
@@ -0,0 +1,281 @@
+// Portable icons, decoded to RGBA in every size the source provides so each
+// backend can pick the one that suits it.
+//
+// Builds with only the mock backend never look at the pixels.
+#![cfg_attr(
+    not(any(
+        target_os = "windows",
+        feature = "appindicator",
+        feature = "sni",
+        feature = "xembed"
+    )),
+    allow(dead_code)
+)]
+
+use crate::Error;
+use std::{fmt, io::Cursor};
+
+// Sizes SVG icons are rendered at, covering the usual tray sizes at scale 1
+// and 2.
+#[cfg(feature = "svg")]
+const SVG_SIZES: &[u32] = &[16, 22, 24, 32, 44, 48, 64, 128, 256];
+
+// One size of an icon, as 8 bit RGBA rows from the top left.
+#[derive(Clone)]
+pub(crate) struct IconImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl IconImage {
+    fn new(rgba: Vec<u8>, width: u32, height: u32) -> Result<IconImage, Error> {
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidIcon(format!("Icon size {}x{} is empty", width, height)));
+        }
+        let expected = width as usize * height as usize * 4;
+        if rgba.len() != expected {
+            return Err(Error::InvalidIcon(format!(
+                "Expected {} bytes of RGBA data for a {}x{} icon, got {}",
+                expected,
+                width,
+                height,
+                rgba.len()
+            )));
+        }
+        Ok(IconImage {
+            width,
+            height,
+            rgba,
+        })
+    }
+
+    fn from_ico(image: ico::IconImage) -> IconImage {
+        IconImage {
+            width: image.width(),
+            height: image.height(),
+            rgba: image.rgba_data().to_vec(),
+        }
+    }
+}
+
+fn invalid<E: fmt::Display>(format: &str, err: E) -> Error {
+    Error::InvalidIcon(format!("Cannot decode {} data: {}", format, err))
+}
+
+/// Icon image in one or more sizes, for `Application::set_icon`.
+///
+/// Backends use the size that fits the tray best, so icons that may be shown
+/// at several sizes or scales look better with more than one.
+#[derive(Clone)]
+pub struct Icon {
+    // Smallest first.
+    pub(crate) images: Vec<IconImage>,
+}
+
+impl Icon {
+    /// Icon with a single size, from `width * height` pixels of 8 bit RGBA
+    /// data, row by row from the top left.
+    pub fn from_rgba(rgba: Vec<u8>, width: u32, height: u32) -> Result<Icon, Error> {
+        Icon::from_images(vec![IconImage::new(rgba, width, height)?])
+    }
+
+    pub fn from_png(data: &[u8]) -> Result<Icon, Error> {
+        let image = ico::IconImage::read_png(data).map_err(|e| invalid("PNG", e))?;
+        Icon::from_images(vec![IconImage::from_ico(image)])
+    }
+
+    /// Keeps every size stored in the ICO file.
+    pub fn from_ico(data: &[u8]) -> Result<Icon, Error> {
+        let dir = ico::IconDir::read(Cursor::new(data)).map_err(|e| invalid("ICO", e))?;
+        let mut images = vec![];
+        for entry in dir.entries() {
+            let image = entry.decode().map_err(|e| invalid("ICO", e))?;
+            images.push(IconImage::from_ico(image));
+        }
+        Icon::from_images(images)
+    }
+
+    /// Renders the SVG at the usual tray icon sizes. Non-square images are
+    /// centered.
+    #[cfg(feature = "svg")]
+    pub fn from_svg(data: &[u8]) -> Result<Icon, Error> {
+        use resvg::{tiny_skia, usvg};
+
+        let tree =
+            usvg::Tree::from_data(data, &usvg::Options::default()).map_err(|e| invalid("SVG", e))?;
+        let (width, height) = (tree.size().width(), tree.size().height());
+        let mut images = vec![];
+        for &size in SVG_SIZES {
+            let mut pixmap = match tiny_skia::Pixmap::new(size, size) {
+                Some(p) => p,
+                None => return Err(Error::InvalidIcon(format!("Cannot render SVG at {}", size))),
+            };
+            let scale = size as f32 / width.max(height);
+            let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(
+                (size as f32 - width * scale) / 2.0,
+                (size as f32 - height * scale) / 2.0,
+            );
+            resvg::render(&tree, transform, &mut pixmap.as_mut());
+            // tiny-skia works with premultiplied alpha.
+            let mut rgba = Vec::with_capacity(size as usize * size as usize * 4);
+            for pixel in pixmap.pixels() {
+                let c = pixel.demultiply();
+                rgba.extend_from_slice(&[c.red(), c.green(), c.blue(), c.alpha()]);
+            }
+            images.push(IconImage::new(rgba, size, size)?);
+        }
+        Icon::from_images(images)
+    }
+
+    /// Decodes PNG, ICO or, with the "svg" feature, SVG data, telling them
+    /// apart by their contents.
+    pub fn from_bytes(data: &[u8]) -> Result<Icon, Error> {
+        if data.starts_with(b"\x89PNG") {
+            Icon::from_png(data)
+        } else if data.starts_with(&[0, 0, 1, 0]) {
+            Icon::from_ico(data)
+        } else {
+            Icon::from_other_bytes(data)
+        }
+    }
+
+    #[cfg(feature = "svg")]
+    fn from_other_bytes(data: &[u8]) -> Result<Icon, Error> {
+        Icon::from_svg(data)
+    }
+
+    #[cfg(not(feature = "svg"))]
+    fn from_other_bytes(_data: &[u8]) -> Result<Icon, Error> {
+        Err(Error::InvalidIcon("Unknown icon format".to_owned()))
+    }
+
+    fn from_images(mut images: Vec<IconImage>) -> Result<Icon, Error> {
+        if images.is_empty() {
+            return Err(Error::InvalidIcon("Icon has no images".to_owned()));
+        }
+        images.sort_by_key(|i| i.width.max(i.height));
+        Ok(Icon { images })
+    }
+
+    // The smallest image that covers `size` pixels, or the largest one if
+    // none does, as scaling down looks better than scaling up.
+    #[cfg_attr(all(target_os = "linux", feature = "sni"), allow(dead_code))]
+    pub(crate) fn best_for_size(&self, size: u32) -> &IconImage {
+        self.images
+            .iter()
+            .find(|i| i.width.max(i.height) >= size)
+            .unwrap_or_else(|| self.largest())
+    }
+
+    #[cfg_attr(all(target_os = "linux", feature = "sni"), allow(dead_code))]
+    fn largest(&self) -> &IconImage {
+        &self.images[self.images.len() - 1]
+    }
+}
+
+impl fmt::Debug for Icon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sizes: Vec<_> = self
+            .images
+            .iter()
+            .map(|i| format!("{}x{}", i.width, i.height))
+            .collect();
+        f.debug_struct("Icon").field("sizes", &sizes).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x1 RGBA PNG: an opaque red pixel, then a half transparent blue one.
+    const PNG: &[u8] = &[
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0xf4,
+        0x22, 0x7f, 0x8a, 0x00, 0x00, 0x00, 0x0e, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0xf8,
+        0xcf, 0xc0, 0x00, 0x42, 0x0d, 0x00, 0x0f, 0x7a, 0x03, 0x7e, 0x6a, 0x81, 0x31, 0xe1, 0x00,
+        0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+    ];
+    const PNG_RGBA: &[u8] = &[255, 0, 0, 255, 0, 0, 255, 128];
+
+    // 16, 24, 32 and 48 pixel BMP entries and a 256 pixel PNG entry.
+    const ICO: &[u8] = include_bytes!("../resources/rust.ico");
+
+    fn sizes(icon: &Icon) -> Vec<(u32, u32)> {
+        icon.images.iter().map(|i| (i.width, i.height)).collect()
+    }
+
+    #[test]
+    fn decodes_png() {
+        let icon = Icon::from_png(PNG).unwrap();
+        assert_eq!(sizes(&icon), vec![(2, 1)]);
+        assert_eq!(icon.images[0].rgba, PNG_RGBA);
+    }
+
+    #[test]
+    fn decodes_every_ico_size() {
+        let icon = Icon::from_ico(ICO).unwrap();
+        assert_eq!(
+            sizes(&icon),
+            vec![(16, 16), (24, 24), (32, 32), (48, 48), (256, 256)]
+        );
+        for image in &icon.images {
+            assert_eq!(image.rgba.len(), (image.width * image.height * 4) as usize);
+        }
+    }
+
+    #[test]
+    fn sniffs_format() {
+        assert_eq!(sizes(&Icon::from_bytes(PNG).unwrap()), vec![(2, 1)]);
+        assert_eq!(Icon::from_bytes(ICO).unwrap().images.len(), 5);
+        assert!(Icon::from_bytes(b"GIF89a").is_err());
+        assert!(Icon::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn picks_best_size() {
+        let icon = Icon::from_ico(ICO).unwrap();
+        let best = |size| icon.best_for_size(size).width;
+        assert_eq!(best(1), 16);
+        assert_eq!(best(16), 16);
+        assert_eq!(best(20), 24);
+        assert_eq!(best(48), 48);
+        assert_eq!(best(64), 256);
+        assert_eq!(best(1024), 256);
+    }
+
+    #[test]
+    fn sorts_images_by_size() {
+        let image = |size: u32| IconImage::new(vec![0; (size * size * 4) as usize], size, size);
+        let icon = Icon::from_images(vec![image(32).unwrap(), image(8).unwrap()]).unwrap();
+        assert_eq!(sizes(&icon), vec![(8, 8), (32, 32)]);
+        assert_eq!(icon.largest().width, 32);
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let mut bad_crc = PNG.to_vec();
+        bad_crc[45] ^= 0xff;
+        let inputs: Vec<&[u8]> = vec![
+            &PNG[..8],
+            &PNG[..40],
+            &bad_crc,
+            &ICO[..6],
+            &ICO[..30],
+            &ICO[..200],
+            // ICO header claiming one entry, with nothing after it.
+            &[0, 0, 1, 0, 1, 0],
+        ];
+        for data in inputs {
+            assert!(matches!(Icon::from_bytes(data), Err(Error::InvalidIcon(_))));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_rgba() {
+        assert!(Icon::from_rgba(vec![0; 15], 2, 2).is_err());
+        assert!(Icon::from_rgba(vec![], 0, 0).is_err());
+        assert!(Icon::from_rgba(vec![0; 16], 2, 2).is_ok());
+    }
+}
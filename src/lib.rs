@@ -1,10 +1,29 @@
 // Systray Lib
 pub mod api;
+mod icon;
+mod menu;
+mod notification;
+#[cfg(feature = "async")]
+mod stream;
+
+#[cfg(feature = "mock")]
+pub use api::mock::{MockIcon, MockItemKind, MockMenuItem, MockTray};
+pub use api::BackendKind;
+pub use icon::Icon;
+pub use menu::{Menu, MenuItem};
+pub use notification::{CloseReason, Notification, Urgency};
+#[cfg(feature = "async")]
+pub use stream::EventStream;
 
 use std::{
     collections::HashMap,
     error, fmt,
-    sync::mpsc::{channel, Receiver},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    task::Waker,
+    time::Duration,
 };
 
 type BoxedError = Box<dyn error::Error + Send + Sync + 'static>;
@@ -14,7 +33,31 @@ pub enum Error {
     OsError(String),
     NotImplementedError,
     UnknownError,
+    InvalidMenuItem(u32),
+    InvalidIcon(String),
     Error(BoxedError),
+    /// The backend in use, or this build, can't do this. `feature` says what
+    /// is missing.
+    Unsupported { feature: &'static str },
+    /// The backend failed to start, `source` says why.
+    BackendInit { source: BoxedError },
+    /// The backend's thread or window is gone, usually after `quit`.
+    BackendGone,
+    /// None of the backends tried could be started, `failures` has each one's
+    /// `BackendInit` error in the order they were tried.
+    NoBackend { failures: Vec<(BackendKind, Error)> },
+}
+
+impl Error {
+    // Keeps the cause of a backend failing to start as the source.
+    pub(crate) fn into_backend_init(self) -> Error {
+        match self {
+            Error::BackendInit { .. } => self,
+            e => Error::BackendInit {
+                source: Box::new(e),
+            },
+        }
+    }
 }
 
 impl From<BoxedError> for Error {
@@ -23,11 +66,287 @@ impl From<BoxedError> for Error {
     }
 }
 
+/// Direction of a scroll over the tray icon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrollOrientation {
+    Horizontal,
+    Vertical,
+}
+
+/// How the tray icon is shown, see `Application::set_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The icon is hidden.
+    Passive,
+    Active,
+    /// The icon asks for the user's attention, showing the attention icon if
+    /// one is set.
+    NeedsAttention,
+}
+
+/// Events reported by the tray, see `Application::wait_for_event`.
+///
+/// Not every platform reports every event. AppIndicator on Linux always opens
+/// the menu when the icon is clicked, so clicks are only reported on Windows
+/// and with the "sni" and "xembed" features, and neither is the menu opening
+/// or closing. Windows has no scroll events for tray icons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A plain menu item was clicked.
+    MenuItemActivated { index: u32 },
+    /// A check or radio item was clicked, and is now in the given state.
+    CheckToggled { index: u32, checked: bool },
+    LeftClick,
+    DoubleClick,
+    MiddleClick,
+    /// Positive deltas scroll down or right.
+    Scroll {
+        delta: i32,
+        orientation: ScrollOrientation,
+    },
+    MenuOpened,
+    MenuClosed,
+    /// A tray host appeared or went away, see `is_tray_available`. Only the
+    /// kind of tray the backend shows its icon in is watched: StatusNotifier
+    /// hosts for the appindicator and sni backends, which needs the "dbus"
+    /// feature, and the XEmbed tray for the xembed backend. On Windows this
+    /// is sent when Explorer restarts and the icon is added back.
+    TrayHostChanged { available: bool },
+    /// A notification from `Application::notify` was clicked, on the action
+    /// with the given index or, without one, anywhere else.
+    NotificationActivated { id: u32, action: Option<u32> },
+    /// A notification from `Application::notify` went away.
+    NotificationClosed { id: u32, reason: CloseReason },
+    /// The platform loop has stopped, no more events will arrive.
+    Shutdown,
+}
+
 pub struct SystrayEvent {
-    menu_index: u32,
+    event: Event,
+}
+
+impl From<Event> for SystrayEvent {
+    fn from(event: Event) -> Self {
+        SystrayEvent { event }
+    }
+}
+
+// Task waiting on Application::events, woken up whenever an event is sent.
+type WakerSlot = Arc<Mutex<Option<Waker>>>;
+
+/// Sending half of the event channel, handed to the platform modules.
+#[derive(Clone)]
+pub struct EventSender {
+    tx: Sender<SystrayEvent>,
+    waker: WakerSlot,
+}
+
+impl EventSender {
+    // Returns false once the application is gone. Builds without any backend
+    // or session bus never send anything.
+    #[cfg_attr(
+        not(any(
+            target_os = "windows",
+            all(
+                target_os = "linux",
+                any(
+                    feature = "sni",
+                    feature = "xembed",
+                    feature = "appindicator",
+                    feature = "dbus"
+                )
+            ),
+            feature = "mock"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn send(&self, event: Event) -> bool {
+        let sent = self.tx.send(SystrayEvent::from(event)).is_ok();
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+        sent
+    }
+}
+
+/// Whether a tray host is there to show the icon. Without one, icons are
+/// created but never shown, and apps may want to fall back to a window or a
+/// notification.
+///
+/// On Linux this asks the StatusNotifierWatcher on the session bus whether a
+/// host is registered with it, with the "dbus" feature, and with the "xembed"
+/// feature also whether an XEmbed tray runs on the X11 display. Watch for
+/// `Event::TrayHostChanged` to learn when the tray your backend uses comes
+/// or goes.
+pub fn is_tray_available() -> bool {
+    api::is_tray_available()
+}
+
+/// Cloneable handle for updating the tray from other threads, see
+/// `Application::handle`.
+///
+/// Changes go straight to the platform, so they show up even while the
+/// application is blocked in `wait_for_message`. Check state is kept by the
+/// `Application`, and can only be changed through it.
+#[derive(Clone)]
+pub struct TrayHandle {
+    handle: Arc<dyn api::BackendHandle>,
+}
+
+impl TrayHandle {
+    pub fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
+        self.handle.set_icon_from_file(file)
+    }
+
+    pub fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.handle.set_icon_from_name(name)
+    }
+
+    pub fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.handle.set_icon(icon)
+    }
+
+    pub fn set_icon_from_rgba(&self, buffer: &[u8], width: u32, height: u32) -> Result<(), Error> {
+        self.set_icon(&Icon::from_rgba(buffer.to_vec(), width, height)?)
+    }
+
+    pub fn set_animated_icon(&self, frames: Vec<Icon>, frame_duration: Duration) -> Result<(), Error> {
+        if frames.is_empty() {
+            return Err(Error::InvalidIcon("Animation has no frames".to_owned()));
+        }
+        self.handle.set_animated_icon(&frames, frame_duration)
+    }
+
+    pub fn stop_animation(&self) -> Result<(), Error> {
+        self.handle.stop_animation()
+    }
+
+    pub fn set_label(&self, label: &str, guide: &str) -> Result<(), Error> {
+        self.handle.set_label(label, guide)
+    }
+
+    pub fn set_status(&self, status: Status) -> Result<(), Error> {
+        self.handle.set_status(status)
+    }
+
+    pub fn set_attention_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.handle.set_attention_icon(icon)
+    }
+
+    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.handle.set_tooltip(tooltip)
+    }
+
+    pub fn set_menu_item_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.handle.set_menu_entry_label(item_idx, item_name)
+    }
+
+    pub fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.handle.set_menu_entry_enabled(item_idx, enabled)
+    }
+
+    /// Stops the platform loop. The application sees `Event::Shutdown`
+    /// once it has stopped.
+    pub fn quit(&self) {
+        self.handle.quit();
+    }
+}
+
+/// Handle to a submenu created with `Application::add_submenu`.
+///
+/// Entries added through the handle show up inside the submenu. Their
+/// callbacks are dispatched by `Application::wait_for_message` just like top
+/// level entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Submenu {
+    idx: u32,
+}
+
+impl Submenu {
+    /// Index of the menu entry that opens this submenu.
+    pub fn index(&self) -> u32 {
+        self.idx
+    }
+
+    pub fn add_menu_item<F, E>(
+        &self,
+        app: &mut Application,
+        item_name: &str,
+        f: F,
+    ) -> Result<u32, Error>
+    where
+        F: FnMut(&mut Application) -> Result<(), E> + Send + Sync + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        app.add_menu_item_in(Some(self.idx), None, item_name, Some(make_callback(f)))
+    }
+
+    pub fn add_menu_separator(&self, app: &mut Application) -> Result<u32, Error> {
+        app.add_menu_separator_in(Some(self.idx), None)
+    }
+
+    pub fn add_submenu(&self, app: &mut Application, item_name: &str) -> Result<Submenu, Error> {
+        app.add_submenu_in(Some(self.idx), None, item_name)
+    }
+
+    pub fn add_check_menu_item<F, E>(
+        &self,
+        app: &mut Application,
+        item_name: &str,
+        checked: bool,
+        f: F,
+    ) -> Result<u32, Error>
+    where
+        F: FnMut(&mut Application, bool) -> Result<(), E> + Send + Sync + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        let f = make_toggle_callback(f);
+        app.add_check_menu_item_in(Some(self.idx), None, None, item_name, checked, Some(f))
+    }
+
+    pub fn add_radio_menu_item<F, E>(
+        &self,
+        app: &mut Application,
+        group: RadioGroup,
+        item_name: &str,
+        checked: bool,
+        f: F,
+    ) -> Result<u32, Error>
+    where
+        F: FnMut(&mut Application, bool) -> Result<(), E> + Send + Sync + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        let f = make_toggle_callback(f);
+        app.add_check_menu_item_in(Some(self.idx), None, Some(group), item_name, checked, Some(f))
+    }
 }
 
-impl error::Error for Error {}
+/// Group of radio menu items, created with `Application::add_radio_group`.
+///
+/// At most one item of a group is checked at a time. Selecting an item
+/// unchecks the rest of its group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RadioGroup {
+    id: u32,
+}
+
+// State of a check or radio item. The application keeps the source of truth
+// and pushes it to the platform, since not every platform toggles items on its
+// own.
+struct CheckItem {
+    group: Option<RadioGroup>,
+    checked: bool,
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Error(ref e) | Error::BackendInit { source: ref e } => Some(e.as_ref()),
+            Error::NoBackend { ref failures } => failures.first().map(|(_, e)| e as _),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -37,19 +356,52 @@ impl fmt::Display for Error {
             OsError(ref err_str) => write!(f, "OsError: {}", err_str),
             NotImplementedError => write!(f, "Functionality is not implemented yet"),
             UnknownError => write!(f, "Unknown error occurrred"),
+            InvalidMenuItem(idx) => write!(
+                f,
+                "Menu item {} does not exist or does not support this operation",
+                idx
+            ),
+            InvalidIcon(ref err_str) => write!(f, "Invalid icon: {}", err_str),
             Error(ref e) => write!(f, "Error: {}", e),
+            Unsupported { feature } => write!(f, "Not supported: {}", feature),
+            BackendInit { ref source } => write!(f, "Tray backend failed to start: {}", source),
+            BackendGone => write!(f, "Tray backend is gone"),
+            NoBackend { ref failures } => {
+                write!(f, "No tray backend could be started")?;
+                for (i, (kind, e)) in failures.iter().enumerate() {
+                    let separator = if i == 0 { " (" } else { ", " };
+                    write!(f, "{}{:?}: {}", separator, kind, e)?;
+                }
+                if failures.is_empty() {
+                    Ok(())
+                } else {
+                    write!(f, ")")
+                }
+            }
         }
     }
 }
 
 pub struct Application {
-    window: api::api::Window,
+    window: Box<dyn api::Backend>,
+    backend: BackendKind,
     menu_idx: u32,
+    // Parent submenu of every live menu entry, None for top level entries.
+    menu_parents: HashMap<u32, Option<u32>>,
     callback: HashMap<u32, Callback>,
+    checks: HashMap<u32, CheckItem>,
+    radio_group_idx: u32,
+    named_radio_groups: HashMap<String, RadioGroup>,
+    // Shadow of the tree applied with set_menu, None until it's first called.
+    menu_tree: Option<Vec<menu::MountedItem>>,
     // Each platform-specific window module will set up its own thread for
     // dealing with the OS main loop. Use this channel for receiving events from
     // that thread.
     rx: Receiver<SystrayEvent>,
+    #[cfg(feature = "async")]
+    waker: WakerSlot,
+    #[cfg(feature = "async")]
+    pending: Vec<stream::PendingCallback>,
 }
 
 type Callback =
@@ -66,47 +418,475 @@ where
     }) as Callback
 }
 
-impl Application {
-    pub fn new() -> Result<Application, Error> {
-        let (event_tx, event_rx) = channel();
-        match api::api::Window::new(event_tx) {
-            Ok(w) => Ok(Application {
+type ToggleCallback =
+    Box<dyn FnMut(&mut Application, bool) -> Result<(), BoxedError> + Send + Sync + 'static>;
+
+fn make_toggle_callback<F, E>(mut f: F) -> ToggleCallback
+where
+    F: FnMut(&mut Application, bool) -> Result<(), E> + Send + Sync + 'static,
+    E: error::Error + Send + Sync + 'static,
+{
+    Box::new(move |a: &mut Application, checked: bool| match f(a, checked) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(Box::new(e) as BoxedError),
+    }) as ToggleCallback
+}
+
+// Check items get their new state passed in, which we look up when the
+// callback runs, after the click has been applied.
+fn bind_toggle_callback(item_idx: u32, mut f: ToggleCallback) -> Callback {
+    Box::new(move |a: &mut Application| {
+        let checked = a.is_menu_item_checked(item_idx)?;
+        f(a, checked)
+    }) as Callback
+}
+
+/// Kind of application behind a tray icon. Some desktops use it to group
+/// or order icons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    ApplicationStatus,
+    Communications,
+    SystemServices,
+    Hardware,
+}
+
+/// Settings the tray icon is created with, see `Application::builder`.
+#[derive(Clone)]
+pub struct ApplicationBuilder {
+    pub(crate) id: String,
+    pub(crate) title: Option<String>,
+    pub(crate) category: Category,
+    pub(crate) icon: Option<Icon>,
+    pub(crate) icon_name: Option<String>,
+    pub(crate) backend: Option<BackendKind>,
+    pub(crate) wait_for_backend: bool,
+}
+
+impl Default for ApplicationBuilder {
+    fn default() -> Self {
+        ApplicationBuilder::new()
+    }
+}
+
+impl ApplicationBuilder {
+    pub fn new() -> ApplicationBuilder {
+        // Desktops tell tray icons apart by their id, so an app that doesn't
+        // pick one still gets one of its own.
+        let id = std::env::current_exe()
+            .ok()
+            .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "systray".to_owned());
+        ApplicationBuilder {
+            id,
+            title: None,
+            category: Category::ApplicationStatus,
+            icon: None,
+            icon_name: None,
+            backend: None,
+            wait_for_backend: false,
+        }
+    }
+
+    /// Unique name of the application, used by the desktop to remember
+    /// where the user placed the icon. Defaults to the executable name.
+    pub fn id(mut self, id: &str) -> ApplicationBuilder {
+        self.id = id.to_owned();
+        self
+    }
+
+    /// Human readable name of the application. Also the initial tooltip.
+    pub fn title(mut self, title: &str) -> ApplicationBuilder {
+        self.title = Some(title.to_owned());
+        self
+    }
+
+    pub fn category(mut self, category: Category) -> ApplicationBuilder {
+        self.category = category;
+        self
+    }
+
+    pub fn icon(mut self, icon: Icon) -> ApplicationBuilder {
+        self.icon = Some(icon);
+        self
+    }
+
+    /// Icon from the desktop icon theme. Only used on Linux, where it takes
+    /// precedence over `icon`.
+    pub fn icon_name(mut self, name: &str) -> ApplicationBuilder {
+        self.icon_name = Some(name.to_owned());
+        self
+    }
+
+    /// Backend to use instead of picking one automatically. Without one,
+    /// every backend available in the build is tried in order, see
+    /// `BackendKind::available`, and the first that starts is used.
+    pub fn backend(mut self, kind: BackendKind) -> ApplicationBuilder {
+        self.backend = Some(kind);
+        self
+    }
+
+    /// Makes changes that the backend carries out on its own thread block
+    /// until they are done, so their errors reach the caller. Off by default,
+    /// where such calls return as soon as the change is queued and errors
+    /// are only logged. Only AppIndicator on Linux queues changes, the other
+    /// backends always report them.
+    pub fn wait_for_backend(mut self, wait: bool) -> ApplicationBuilder {
+        self.wait_for_backend = wait;
+        self
+    }
+
+    pub fn build(self) -> Result<Application, Error> {
+        let (tx, event_rx) = channel();
+        let waker = WakerSlot::default();
+        let event_tx = EventSender {
+            tx,
+            waker: waker.clone(),
+        };
+        match api::create(self.backend, event_tx, self) {
+            Ok((w, backend)) => Ok(Application {
                 window: w,
+                backend,
                 menu_idx: 0,
+                menu_parents: HashMap::new(),
                 callback: HashMap::new(),
+                checks: HashMap::new(),
+                radio_group_idx: 0,
+                named_radio_groups: HashMap::new(),
+                menu_tree: None,
                 rx: event_rx,
+                #[cfg(feature = "async")]
+                waker,
+                #[cfg(feature = "async")]
+                pending: vec![],
             }),
             Err(e) => Err(e),
         }
     }
+}
+
+impl Application {
+    pub fn new() -> Result<Application, Error> {
+        ApplicationBuilder::new().build()
+    }
+
+    pub fn builder() -> ApplicationBuilder {
+        ApplicationBuilder::new()
+    }
+
+    /// Application using the given backend, failing if it can't start.
+    pub fn with_backend(kind: BackendKind) -> Result<Application, Error> {
+        ApplicationBuilder::new().backend(kind).build()
+    }
+
+    /// The backend in use, which `new` picks at runtime.
+    pub fn backend(&self) -> BackendKind {
+        self.backend
+    }
+
+    /// Recorded state of the mock backend, which tests can also use to
+    /// click menu items. None with any other backend.
+    #[cfg(feature = "mock")]
+    pub fn mock(&self) -> Option<MockTray> {
+        self.window.mock()
+    }
 
     pub fn add_menu_item<F, E>(&mut self, item_name: &str, f: F) -> Result<u32, Error>
     where
         F: FnMut(&mut Application) -> Result<(), E> + Send + Sync + 'static,
         E: error::Error + Send + Sync + 'static,
     {
+        self.add_menu_item_in(None, None, item_name, Some(make_callback(f)))
+    }
+
+    pub fn add_menu_separator(&mut self) -> Result<u32, Error> {
+        self.add_menu_separator_in(None, None)
+    }
+
+    pub fn add_submenu(&mut self, item_name: &str) -> Result<Submenu, Error> {
+        self.add_submenu_in(None, None, item_name)
+    }
+
+    /// Inserts a menu item at `position` in the top level menu, moving the
+    /// entries after it down. Positions past the end append the item.
+    pub fn insert_menu_item_at<F, E>(
+        &mut self,
+        position: u32,
+        item_name: &str,
+        f: F,
+    ) -> Result<u32, Error>
+    where
+        F: FnMut(&mut Application) -> Result<(), E> + Send + Sync + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        self.add_menu_item_in(None, Some(position), item_name, Some(make_callback(f)))
+    }
+
+    /// Removes a menu entry. Removing a submenu also removes everything in
+    /// it. The index of a removed entry is never handed out again.
+    pub fn remove_menu_item(&mut self, item_idx: u32) -> Result<(), Error> {
+        self.check_menu_item(item_idx)?;
+        // Children go first, so the platform never sees an entry whose parent
+        // is already gone.
+        for idx in self.menu_item_tree(item_idx) {
+            self.window.remove_menu_entry(idx)?;
+            self.menu_parents.remove(&idx);
+            self.callback.remove(&idx);
+            self.checks.remove(&idx);
+        }
+        Ok(())
+    }
+
+    /// Replaces the menu with `menu`.
+    ///
+    /// The first call removes the entries that were added one by one. After
+    /// that, the new tree is compared against the previous one and only the
+    /// entries that changed are touched on the platform side. Callbacks are
+    /// always taken from the newest tree.
+    pub fn set_menu(&mut self, menu: Menu) -> Result<(), Error> {
+        let old = match self.menu_tree.take() {
+            Some(tree) => tree,
+            None => {
+                self.clear_menu()?;
+                vec![]
+            }
+        };
+        // On error the shadow tree is left unset, so the next call starts
+        // over from an empty menu.
+        let tree = menu::apply_menu(self, None, old, menu)?;
+        self.menu_tree = Some(tree);
+        Ok(())
+    }
+
+    /// Index of the entry with the given id in the tree applied with
+    /// `set_menu`.
+    pub fn menu_item_by_id(&self, id: &str) -> Option<u32> {
+        match self.menu_tree {
+            Some(ref tree) => menu::find_menu_item(tree, id),
+            None => None,
+        }
+    }
+
+    /// Removes every menu entry.
+    pub fn clear_menu(&mut self) -> Result<(), Error> {
+        self.menu_tree = None;
+        let top_level: Vec<u32> = self
+            .menu_parents
+            .iter()
+            .filter(|(_, parent)| parent.is_none())
+            .map(|(idx, _)| *idx)
+            .collect();
+        for idx in top_level {
+            self.remove_menu_item(idx)?;
+        }
+        Ok(())
+    }
+
+    // Returns the entry and everything below it, deepest entries first.
+    fn menu_item_tree(&self, item_idx: u32) -> Vec<u32> {
+        let mut tree = vec![];
+        for (idx, parent) in &self.menu_parents {
+            if *parent == Some(item_idx) {
+                tree.extend(self.menu_item_tree(*idx));
+            }
+        }
+        tree.push(item_idx);
+        tree
+    }
+
+    pub fn add_check_menu_item<F, E>(
+        &mut self,
+        item_name: &str,
+        checked: bool,
+        f: F,
+    ) -> Result<u32, Error>
+    where
+        F: FnMut(&mut Application, bool) -> Result<(), E> + Send + Sync + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        let f = make_toggle_callback(f);
+        self.add_check_menu_item_in(None, None, None, item_name, checked, Some(f))
+    }
+
+    pub fn add_radio_group(&mut self) -> RadioGroup {
+        let group = RadioGroup {
+            id: self.radio_group_idx,
+        };
+        self.radio_group_idx += 1;
+        group
+    }
+
+    pub fn add_radio_menu_item<F, E>(
+        &mut self,
+        group: RadioGroup,
+        item_name: &str,
+        checked: bool,
+        f: F,
+    ) -> Result<u32, Error>
+    where
+        F: FnMut(&mut Application, bool) -> Result<(), E> + Send + Sync + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        let f = make_toggle_callback(f);
+        self.add_check_menu_item_in(None, None, Some(group), item_name, checked, Some(f))
+    }
+
+    pub fn is_menu_item_checked(&self, item_idx: u32) -> Result<bool, Error> {
+        match self.checks.get(&item_idx) {
+            Some(c) => Ok(c.checked),
+            None => Err(Error::InvalidMenuItem(item_idx)),
+        }
+    }
+
+    pub fn set_menu_item_checked(&mut self, item_idx: u32, checked: bool) -> Result<(), Error> {
+        let group = match self.checks.get(&item_idx) {
+            Some(c) => c.group,
+            None => return Err(Error::InvalidMenuItem(item_idx)),
+        };
+        if checked && group.is_some() {
+            let others: Vec<u32> = self
+                .checks
+                .iter()
+                .filter(|(i, c)| **i != item_idx && c.group == group && c.checked)
+                .map(|(i, _)| *i)
+                .collect();
+            for i in others {
+                self.window.set_menu_entry_checked(i, false)?;
+                if let Some(c) = self.checks.get_mut(&i) {
+                    c.checked = false;
+                }
+            }
+        }
+        self.window.set_menu_entry_checked(item_idx, checked)?;
+        if let Some(c) = self.checks.get_mut(&item_idx) {
+            c.checked = checked;
+        }
+        Ok(())
+    }
+
+    pub fn set_menu_item_enabled(&self, item_idx: u32, enabled: bool) -> Result<(), Error> {
+        self.check_menu_item(item_idx)?;
+        self.window.set_menu_entry_enabled(item_idx, enabled)
+    }
+
+    pub fn set_menu_item_visible(&self, item_idx: u32, visible: bool) -> Result<(), Error> {
+        self.check_menu_item(item_idx)?;
+        self.window.set_menu_entry_visible(item_idx, visible)
+    }
+
+    pub fn set_menu_item_label(&self, item_idx: u32, item_name: &str) -> Result<(), Error> {
+        self.check_menu_item(item_idx)?;
+        self.window.set_menu_entry_label(item_idx, item_name)
+    }
+
+    fn check_menu_item(&self, item_idx: u32) -> Result<(), Error> {
+        if self.menu_parents.contains_key(&item_idx) {
+            Ok(())
+        } else {
+            Err(Error::InvalidMenuItem(item_idx))
+        }
+    }
+
+    // Applies a click on a check or radio item. Check items flip, radio items
+    // always end up selected.
+    fn toggle_check_item(&mut self, item_idx: u32) -> Result<(), Error> {
+        let checked = match self.checks.get(&item_idx) {
+            Some(c) if c.group.is_some() => true,
+            Some(c) => !c.checked,
+            None => return Ok(()),
+        };
+        self.set_menu_item_checked(item_idx, checked)
+    }
+
+    fn add_menu_item_in(
+        &mut self,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+        f: Option<Callback>,
+    ) -> Result<u32, Error> {
         let idx = self.menu_idx;
-        if let Err(e) = self.window.add_menu_entry(idx, item_name) {
-            return Err(e);
+        self.window.add_menu_entry(idx, parent, position, item_name)?;
+        self.menu_parents.insert(idx, parent);
+        if let Some(f) = f {
+            self.callback.insert(idx, f);
         }
-        self.callback.insert(idx, make_callback(f));
         self.menu_idx += 1;
         Ok(idx)
     }
 
-    pub fn add_menu_separator(&mut self) -> Result<u32, Error> {
+    fn add_menu_separator_in(
+        &mut self,
+        parent: Option<u32>,
+        position: Option<u32>,
+    ) -> Result<u32, Error> {
         let idx = self.menu_idx;
-        if let Err(e) = self.window.add_menu_separator(idx) {
-            return Err(e);
+        self.window.add_menu_separator(idx, parent, position)?;
+        self.menu_parents.insert(idx, parent);
+        self.menu_idx += 1;
+        Ok(idx)
+    }
+
+    fn add_check_menu_item_in(
+        &mut self,
+        parent: Option<u32>,
+        position: Option<u32>,
+        group: Option<RadioGroup>,
+        item_name: &str,
+        checked: bool,
+        f: Option<ToggleCallback>,
+    ) -> Result<u32, Error> {
+        let idx = self.menu_idx;
+        self.window.add_check_menu_entry(
+            idx,
+            parent,
+            position,
+            item_name,
+            checked,
+            group.is_some(),
+        )?;
+        self.menu_parents.insert(idx, parent);
+        if let Some(f) = f {
+            self.callback.insert(idx, bind_toggle_callback(idx, f));
         }
+        self.checks.insert(idx, CheckItem { group, checked });
         self.menu_idx += 1;
+        if checked && group.is_some() {
+            self.set_menu_item_checked(idx, true)?;
+        }
         Ok(idx)
     }
 
+    fn add_submenu_in(
+        &mut self,
+        parent: Option<u32>,
+        position: Option<u32>,
+        item_name: &str,
+    ) -> Result<Submenu, Error> {
+        let idx = self.menu_idx;
+        self.window.add_submenu(idx, parent, position, item_name)?;
+        self.menu_parents.insert(idx, parent);
+        self.menu_idx += 1;
+        Ok(Submenu { idx })
+    }
+
     pub fn set_icon_from_file(&self, file: &str) -> Result<(), Error> {
         self.window.set_icon_from_file(file)
     }
 
+    /// Sets the icon by its name in the desktop icon theme, so the desktop
+    /// picks the size and variant. Only supported on Linux.
+    pub fn set_icon_from_name(&self, name: &str) -> Result<(), Error> {
+        self.window.set_icon_from_name(name)
+    }
+
+    /// Adds a directory to look up icon names in, laid out like an icon
+    /// theme (for example `hicolor/48x48/apps/name.png`). Only supported on
+    /// Linux.
+    pub fn set_icon_theme_path(&self, path: &str) -> Result<(), Error> {
+        self.window.set_icon_theme_path(path)
+    }
+
     pub fn set_icon_from_resource(&self, resource: &str) -> Result<(), Error> {
         self.window.set_icon_from_resource(resource)
     }
@@ -121,37 +901,219 @@ impl Application {
         self.window.set_icon_from_buffer(buffer, width, height)
     }
 
+    pub fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.window.set_icon(icon)
+    }
+
+    /// Sets the icon from `width * height` pixels of 8 bit RGBA data, row by
+    /// row from the top left.
+    pub fn set_icon_from_rgba(&self, buffer: &[u8], width: u32, height: u32) -> Result<(), Error> {
+        self.set_icon(&Icon::from_rgba(buffer.to_vec(), width, height)?)
+    }
+
+    /// Cycles through `frames`, showing each one for `frame_duration`. The
+    /// animation runs on the platform thread until another icon is set or
+    /// `stop_animation` is called.
+    pub fn set_animated_icon(&self, frames: Vec<Icon>, frame_duration: Duration) -> Result<(), Error> {
+        self.handle().set_animated_icon(frames, frame_duration)
+    }
+
+    /// Stops the animation, leaving its current frame up.
+    pub fn stop_animation(&self) -> Result<(), Error> {
+        self.handle().stop_animation()
+    }
+
+    /// Shows `label` next to the icon, or removes it if empty. `guide` is the
+    /// longest text the label is expected to hold, so the panel can keep its
+    /// width steady. Only supported on Linux.
+    pub fn set_label(&self, label: &str, guide: &str) -> Result<(), Error> {
+        self.handle().set_label(label, guide)
+    }
+
+    /// Shows, hides or highlights the icon. The desktop decides how an icon
+    /// that needs attention looks, on Windows it shows the attention icon.
+    pub fn set_status(&self, status: Status) -> Result<(), Error> {
+        self.handle().set_status(status)
+    }
+
+    /// Icon shown instead of the normal one while the status is
+    /// `Status::NeedsAttention`.
+    pub fn set_attention_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.handle().set_attention_icon(icon)
+    }
+
     pub fn shutdown(&self) -> Result<(), Error> {
         self.window.shutdown()
     }
 
+    /// Sets the text shown when hovering the icon. The appindicator backend
+    /// has no tooltips and only sets the indicator title, which few panels
+    /// show anywhere; use the sni or xembed backend for a real tooltip.
     pub fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
         self.window.set_tooltip(tooltip)
     }
 
+    /// Shows a desktop notification and returns its id, which the events about
+    /// it carry. On Linux this goes through the desktop's notification server
+    /// on the session bus, on Windows it is a balloon from the tray icon.
+    pub fn notify(&self, notification: Notification) -> Result<u32, Error> {
+        self.window.notify(&notification)
+    }
+
+    pub fn handle(&self) -> TrayHandle {
+        TrayHandle {
+            handle: self.window.handle(),
+        }
+    }
+
     pub fn quit(&mut self) {
         self.window.quit()
     }
 
+    /// Blocks until the next event, runs the callbacks attached to it and
+    /// returns it.
+    pub fn wait_for_event(&mut self) -> Result<Event, Error> {
+        match self.recv_event(None) {
+            Some(event) => self.dispatch(event),
+            None => Ok(Event::Shutdown),
+        }
+    }
+
+    /// Handles the events that are already queued and returns them, without
+    /// waiting for new ones.
+    pub fn try_dispatch(&mut self) -> Result<Vec<Event>, Error> {
+        self.try_iter().collect()
+    }
+
+    /// Waits up to `timeout` for an event, then handles it along with any
+    /// other queued events. Returns an empty list if the timeout expired.
+    pub fn dispatch_timeout(&mut self, timeout: Duration) -> Result<Vec<Event>, Error> {
+        let event = match self.recv_event(Some(timeout)) {
+            Some(event) => self.dispatch(event)?,
+            None => return Ok(vec![]),
+        };
+        let mut events = vec![event];
+        if event != Event::Shutdown {
+            events.extend(self.try_dispatch()?);
+        }
+        Ok(events)
+    }
+
+    /// Iterator that waits for and handles events, and ends after
+    /// `Event::Shutdown`.
+    pub fn iter(&mut self) -> Iter<'_> {
+        Iter {
+            app: self,
+            done: false,
+        }
+    }
+
+    /// Iterator over the events that are already queued. It ends as soon as
+    /// the queue is empty.
+    pub fn try_iter(&mut self) -> TryIter<'_> {
+        TryIter {
+            app: self,
+            done: false,
+        }
+    }
+
+    // Takes the next event off the channel, waiting up to `timeout` for one,
+    // or forever if there is no timeout. A closed channel means the platform
+    // thread is gone, which is reported as a shutdown.
+    fn recv_event(&mut self, timeout: Option<Duration>) -> Option<Event> {
+        let disconnected = match timeout {
+            None => match self.rx.recv() {
+                Ok(m) => return Some(m.event),
+                Err(_) => true,
+            },
+            Some(t) if t == Duration::from_secs(0) => match self.rx.try_recv() {
+                Ok(m) => return Some(m.event),
+                Err(e) => e == TryRecvError::Disconnected,
+            },
+            Some(t) => match self.rx.recv_timeout(t) {
+                Ok(m) => return Some(m.event),
+                Err(e) => e == RecvTimeoutError::Disconnected,
+            },
+        };
+        if disconnected {
+            self.quit();
+            Some(Event::Shutdown)
+        } else {
+            None
+        }
+    }
+
     pub fn wait_for_message(&mut self) -> Result<(), Error> {
-        loop {
-            let msg;
-            match self.rx.recv() {
-                Ok(m) => msg = m,
-                Err(_) => {
-                    self.quit();
-                    break;
-                }
+        while self.wait_for_event()? != Event::Shutdown {}
+        Ok(())
+    }
+
+    // Runs the callback for a menu event. Clicks on check items are applied
+    // and reported as CheckToggled.
+    fn dispatch(&mut self, event: Event) -> Result<Event, Error> {
+        let index = match event {
+            Event::MenuItemActivated { index } => index,
+            _ => return Ok(event),
+        };
+        let event = if self.checks.contains_key(&index) {
+            self.toggle_check_item(index)?;
+            Event::CheckToggled {
+                index,
+                checked: self.is_menu_item_checked(index)?,
             }
-            if self.callback.contains_key(&msg.menu_index) {
-                if let Some(mut f) = self.callback.remove(&msg.menu_index) {
-                    f(self)?;
-                    self.callback.insert(msg.menu_index, f);
-                }
+        } else {
+            event
+        };
+        if let Some(mut f) = self.callback.remove(&index) {
+            f(self)?;
+            // The callback may have removed its own menu item, or replaced
+            // itself through set_menu.
+            if self.menu_parents.contains_key(&index) {
+                self.callback.entry(index).or_insert(f);
             }
         }
+        Ok(event)
+    }
+}
 
-        Ok(())
+/// Blocking event iterator, see `Application::iter`.
+pub struct Iter<'a> {
+    app: &'a mut Application,
+    done: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let event = self.app.wait_for_event();
+        self.done = matches!(event, Ok(Event::Shutdown));
+        Some(event)
+    }
+}
+
+/// Non-blocking event iterator, see `Application::try_iter`.
+pub struct TryIter<'a> {
+    app: &'a mut Application,
+    done: bool,
+}
+
+impl<'a> Iterator for TryIter<'a> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let event = match self.app.recv_event(Some(Duration::from_secs(0))) {
+            Some(event) => self.app.dispatch(event),
+            None => return None,
+        };
+        self.done = matches!(event, Ok(Event::Shutdown));
+        Some(event)
     }
 }
 
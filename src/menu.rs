@@ -0,0 +1,356 @@
+// Declarative menu trees, applied with Application::set_menu.
+//
+// The application keeps a shadow copy of the last tree it applied. Setting a
+// new tree walks both level by level, keeps entries whose key (kind plus id or
+// label) matches, updates the properties that changed in place and only
+// creates or removes platform entries for the rest.
+
+use crate::{
+    make_callback, make_toggle_callback, Application, Callback, Error, RadioGroup, ToggleCallback,
+};
+use std::{collections::HashMap, error};
+
+/// A whole menu, or the contents of a submenu.
+#[derive(Default)]
+pub struct Menu {
+    items: Vec<MenuItem>,
+}
+
+impl Menu {
+    pub fn new() -> Menu {
+        Menu { items: vec![] }
+    }
+
+    /// Appends an item, for building menus in one expression.
+    pub fn item(mut self, item: MenuItem) -> Menu {
+        self.items.push(item);
+        self
+    }
+
+    pub fn push(&mut self, item: MenuItem) {
+        self.items.push(item);
+    }
+}
+
+impl From<Vec<MenuItem>> for Menu {
+    fn from(items: Vec<MenuItem>) -> Menu {
+        Menu { items }
+    }
+}
+
+enum MenuItemKind {
+    Item,
+    Separator,
+    Submenu(Menu),
+    Check,
+    Radio(String),
+}
+
+enum MenuItemCallback {
+    Activate(Callback),
+    Toggle(ToggleCallback),
+}
+
+/// One entry of a `Menu`.
+///
+/// Entries are matched against the previously applied tree by their id, or by
+/// their label if they have no id. Give entries whose label changes an id, so
+/// they are updated in place instead of being recreated.
+pub struct MenuItem {
+    kind: MenuItemKind,
+    id: Option<String>,
+    label: String,
+    enabled: bool,
+    visible: bool,
+    checked: bool,
+    callback: Option<MenuItemCallback>,
+}
+
+impl MenuItem {
+    fn with_kind(kind: MenuItemKind, label: &str) -> MenuItem {
+        MenuItem {
+            kind,
+            id: None,
+            label: label.to_owned(),
+            enabled: true,
+            visible: true,
+            checked: false,
+            callback: None,
+        }
+    }
+
+    pub fn new(label: &str) -> MenuItem {
+        MenuItem::with_kind(MenuItemKind::Item, label)
+    }
+
+    pub fn separator() -> MenuItem {
+        MenuItem::with_kind(MenuItemKind::Separator, "")
+    }
+
+    pub fn submenu(label: &str, menu: Menu) -> MenuItem {
+        MenuItem::with_kind(MenuItemKind::Submenu(menu), label)
+    }
+
+    pub fn check(label: &str, checked: bool) -> MenuItem {
+        let mut item = MenuItem::with_kind(MenuItemKind::Check, label);
+        item.checked = checked;
+        item
+    }
+
+    /// Radio item in the group with the given name. Groups with the same
+    /// name are shared across the whole tree.
+    pub fn radio(label: &str, group: &str, checked: bool) -> MenuItem {
+        let mut item = MenuItem::with_kind(MenuItemKind::Radio(group.to_owned()), label);
+        item.checked = checked;
+        item
+    }
+
+    pub fn id(mut self, id: &str) -> MenuItem {
+        self.id = Some(id.to_owned());
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> MenuItem {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn visible(mut self, visible: bool) -> MenuItem {
+        self.visible = visible;
+        self
+    }
+
+    /// Callback for plain items.
+    pub fn on_activate<F, E>(mut self, f: F) -> MenuItem
+    where
+        F: FnMut(&mut Application) -> Result<(), E> + Send + Sync + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        self.callback = Some(MenuItemCallback::Activate(make_callback(f)));
+        self
+    }
+
+    /// Callback for check and radio items, gets the new check state.
+    pub fn on_toggle<F, E>(mut self, f: F) -> MenuItem
+    where
+        F: FnMut(&mut Application, bool) -> Result<(), E> + Send + Sync + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        self.callback = Some(MenuItemCallback::Toggle(make_toggle_callback(f)));
+        self
+    }
+
+    fn key(&self) -> ItemKey {
+        let kind = match self.kind {
+            MenuItemKind::Item => KeyKind::Item,
+            MenuItemKind::Separator => KeyKind::Separator,
+            MenuItemKind::Submenu(_) => KeyKind::Submenu,
+            MenuItemKind::Check => KeyKind::Check,
+            MenuItemKind::Radio(ref group) => KeyKind::Radio(group.clone()),
+        };
+        let name = match self.id {
+            Some(ref id) => id.clone(),
+            None => self.label.clone(),
+        };
+        ItemKey { kind, name }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum KeyKind {
+    Item,
+    Separator,
+    Submenu,
+    Check,
+    Radio(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ItemKey {
+    kind: KeyKind,
+    name: String,
+}
+
+// What we know about an entry of the applied tree, without the callbacks,
+// which live in Application::callback.
+pub(crate) struct MountedItem {
+    idx: u32,
+    key: ItemKey,
+    id: Option<String>,
+    label: String,
+    enabled: bool,
+    visible: bool,
+    children: Vec<MountedItem>,
+}
+
+pub(crate) fn find_menu_item(items: &[MountedItem], id: &str) -> Option<u32> {
+    for item in items {
+        if item.id.as_ref().map(|i| i == id).unwrap_or(false) {
+            return Some(item.idx);
+        }
+        if let Some(idx) = find_menu_item(&item.children, id) {
+            return Some(idx);
+        }
+    }
+    None
+}
+
+// Brings the children of `parent` from the `old` state to `new`, returning
+// the new shadow state of that level.
+pub(crate) fn apply_menu(
+    app: &mut Application,
+    parent: Option<u32>,
+    old: Vec<MountedItem>,
+    new: Menu,
+) -> Result<Vec<MountedItem>, Error> {
+    // Drop entries that can't be reused, either because the app removed them
+    // behind our back or because nothing in the new level has their key.
+    let mut wanted: HashMap<ItemKey, usize> = HashMap::new();
+    for item in &new.items {
+        *wanted.entry(item.key()).or_insert(0) += 1;
+    }
+    let mut current = vec![];
+    for item in old {
+        if !app.menu_parents.contains_key(&item.idx) {
+            continue;
+        }
+        match wanted.get_mut(&item.key) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                current.push(item);
+            }
+            _ => app.remove_menu_item(item.idx)?,
+        }
+    }
+
+    // Everything left in `current` is wanted, but possibly in another order.
+    // Entries that are in place get updated, entries that moved get recreated
+    // at their new position.
+    let mut mounted = vec![];
+    for (position, item) in new.items.into_iter().enumerate() {
+        let key = item.key();
+        match current.iter().position(|c| c.key == key) {
+            Some(0) => {
+                let old = current.remove(0);
+                mounted.push(update_item(app, old, item)?);
+            }
+            Some(i) => {
+                let old = current.remove(i);
+                app.remove_menu_item(old.idx)?;
+                mounted.push(create_item(app, parent, position as u32, item)?);
+            }
+            None => mounted.push(create_item(app, parent, position as u32, item)?),
+        }
+    }
+    Ok(mounted)
+}
+
+fn radio_group(app: &mut Application, name: &str) -> RadioGroup {
+    if let Some(group) = app.named_radio_groups.get(name) {
+        return *group;
+    }
+    let group = app.add_radio_group();
+    app.named_radio_groups.insert(name.to_owned(), group);
+    group
+}
+
+fn create_item(
+    app: &mut Application,
+    parent: Option<u32>,
+    position: u32,
+    item: MenuItem,
+) -> Result<MountedItem, Error> {
+    let key = item.key();
+    let (activate, toggle) = match item.callback {
+        Some(MenuItemCallback::Activate(f)) => (Some(f), None),
+        Some(MenuItemCallback::Toggle(f)) => (None, Some(f)),
+        None => (None, None),
+    };
+    let position = Some(position);
+    let mut children = vec![];
+    let idx = match item.kind {
+        MenuItemKind::Item => app.add_menu_item_in(parent, position, &item.label, activate)?,
+        MenuItemKind::Separator => app.add_menu_separator_in(parent, position)?,
+        MenuItemKind::Submenu(menu) => {
+            let submenu = app.add_submenu_in(parent, position, &item.label)?;
+            children = apply_menu(app, Some(submenu.idx), vec![], menu)?;
+            submenu.idx
+        }
+        MenuItemKind::Check => {
+            app.add_check_menu_item_in(parent, position, None, &item.label, item.checked, toggle)?
+        }
+        MenuItemKind::Radio(ref group) => {
+            let group = radio_group(app, group);
+            app.add_check_menu_item_in(
+                parent,
+                position,
+                Some(group),
+                &item.label,
+                item.checked,
+                toggle,
+            )?
+        }
+    };
+    if !item.enabled {
+        app.set_menu_item_enabled(idx, false)?;
+    }
+    if !item.visible {
+        app.set_menu_item_visible(idx, false)?;
+    }
+    Ok(MountedItem {
+        idx,
+        key,
+        id: item.id,
+        label: item.label,
+        enabled: item.enabled,
+        visible: item.visible,
+        children,
+    })
+}
+
+fn update_item(
+    app: &mut Application,
+    mut old: MountedItem,
+    item: MenuItem,
+) -> Result<MountedItem, Error> {
+    let idx = old.idx;
+    if old.label != item.label {
+        app.set_menu_item_label(idx, &item.label)?;
+        old.label = item.label;
+    }
+    if old.enabled != item.enabled {
+        app.set_menu_item_enabled(idx, item.enabled)?;
+        old.enabled = item.enabled;
+    }
+    if old.visible != item.visible {
+        app.set_menu_item_visible(idx, item.visible)?;
+        old.visible = item.visible;
+    }
+    match item.kind {
+        MenuItemKind::Check | MenuItemKind::Radio(_) => {
+            if app.is_menu_item_checked(idx)? != item.checked {
+                app.set_menu_item_checked(idx, item.checked)?;
+            }
+        }
+        MenuItemKind::Submenu(menu) => {
+            let children = std::mem::take(&mut old.children);
+            old.children = apply_menu(app, Some(idx), children, menu)?;
+        }
+        MenuItemKind::Item | MenuItemKind::Separator => {}
+    }
+    // Callbacks always come from the newest tree, so they can capture the
+    // state it was built from.
+    app.callback.remove(&idx);
+    match item.callback {
+        Some(MenuItemCallback::Activate(f)) => {
+            app.callback.insert(idx, f);
+        }
+        Some(MenuItemCallback::Toggle(f)) => {
+            app.callback
+                .insert(idx, crate::bind_toggle_callback(idx, f));
+        }
+        None => {}
+    }
+    old.id = item.id;
+    Ok(old)
+}
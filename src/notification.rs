@@ -0,0 +1,58 @@
+// Desktop notifications, shown with Application::notify. What happens to them
+// afterwards comes back as NotificationActivated and NotificationClosed events.
+
+use crate::Icon;
+use std::time::Duration;
+
+/// How urgent a `Notification` is. Desktops may keep urgent ones up until they
+/// are dismissed, or show them over full screen windows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+/// Why a notification went away, see `Event::NotificationClosed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The timeout ran out.
+    Expired,
+    /// The user closed it.
+    Dismissed,
+    /// The desktop closed it for some other reason.
+    Closed,
+    /// The desktop didn't say. Windows reports balloons that timed out and
+    /// balloons the user closed alike, as this.
+    Unknown,
+}
+
+/// A desktop notification, see `Application::notify`.
+///
+/// On Windows it is shown as a balloon from the tray icon, which has no
+/// action buttons.
+#[derive(Clone, Debug, Default)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    /// Shown next to the text instead of the application icon, where the
+    /// desktop supports it.
+    pub icon: Option<Icon>,
+    pub urgency: Urgency,
+    /// How long the notification stays up, the desktop's default if None.
+    pub timeout: Option<Duration>,
+    /// Labels of the buttons shown on the notification. Clicks on them are
+    /// reported with their index, see `Event::NotificationActivated`.
+    pub actions: Vec<String>,
+}
+
+impl Notification {
+    pub fn new(title: &str, body: &str) -> Notification {
+        Notification {
+            title: title.to_owned(),
+            body: body.to_owned(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Async event handling, enabled with the "async" feature.
+//
+// The platform threads wake the task polling the stream whenever they send an
+// event (see EventSender), so nothing here polls on a timer.
+
+use crate::{Application, BoxedError, Callback, Error, Event};
+use futures_core::Stream;
+use std::{
+    error,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+pub(crate) type PendingCallback =
+    Pin<Box<dyn Future<Output = Result<(), BoxedError>> + Send + 'static>>;
+
+/// Stream of tray events, see `Application::events`.
+pub struct EventStream<'a> {
+    app: &'a mut Application,
+    done: bool,
+}
+
+impl Application {
+    /// Stream that handles events like `Application::iter`, for use on an
+    /// async executor. It also drives the futures started by callbacks added
+    /// with `add_menu_item_async`, and ends after `Event::Shutdown`.
+    pub fn events(&mut self) -> EventStream<'_> {
+        EventStream {
+            app: self,
+            done: false,
+        }
+    }
+
+    /// Adds a menu item whose callback returns a future. The future is run
+    /// while the `events` stream is polled.
+    pub fn add_menu_item_async<F, Fut, E>(
+        &mut self,
+        item_name: &str,
+        mut f: F,
+    ) -> Result<u32, Error>
+    where
+        F: FnMut() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: error::Error + Send + Sync + 'static,
+    {
+        let callback = Box::new(move |a: &mut Application| {
+            let fut = f();
+            a.pending.push(Box::pin(async move {
+                fut.await.map_err(|e| Box::new(e) as BoxedError)
+            }));
+            Ok(())
+        }) as Callback;
+        self.add_menu_item_in(None, None, item_name, Some(callback))
+    }
+
+    // Polls the futures started by async callbacks and drops the finished
+    // ones. Returns the first error, after polling all of them.
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Result<(), Error> {
+        let mut result = Ok(());
+        let mut i = 0;
+        while i < self.pending.len() {
+            match self.pending[i].as_mut().poll(cx) {
+                Poll::Pending => i += 1,
+                Poll::Ready(r) => {
+                    drop(self.pending.swap_remove(i));
+                    if let Err(e) = r {
+                        if result.is_ok() {
+                            result = Err(Error::from(e));
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<'a> Stream for EventStream<'a> {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        // Register before looking at the channel, so an event sent in between
+        // still wakes us up.
+        *this.app.waker.lock().unwrap() = Some(cx.waker().clone());
+        let event = match this.app.recv_event(Some(Duration::from_secs(0))) {
+            Some(event) => this.app.dispatch(event),
+            None => {
+                return match this.app.poll_pending(cx) {
+                    Ok(()) => Poll::Pending,
+                    Err(e) => Poll::Ready(Some(Err(e))),
+                }
+            }
+        };
+        // The callbacks may have started new futures, get polled again so
+        // they make progress.
+        cx.waker().wake_by_ref();
+        this.done = matches!(event, Ok(Event::Shutdown));
+        Poll::Ready(Some(event))
+    }
+}
@@ -0,0 +1,209 @@
+// Applies menu trees one after the other with Application::set_menu and
+// checks what the mock backend ends up with.
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use systray::{
+    Application, BackendKind, Error, Menu, MenuItem, MockItemKind, MockMenuItem, MockTray,
+};
+
+fn mock_app() -> (Application, MockTray) {
+    let app = Application::with_backend(BackendKind::Mock).unwrap();
+    let tray = app.mock().unwrap();
+    (app, tray)
+}
+
+fn labels(items: &[MockMenuItem]) -> Vec<&str> {
+    items.iter().map(|i| i.label.as_str()).collect()
+}
+
+fn indices(items: &[MockMenuItem]) -> Vec<u32> {
+    items.iter().map(|i| i.index).collect()
+}
+
+fn items(labels: &[&str]) -> Menu {
+    labels
+        .iter()
+        .map(|l| MenuItem::new(l))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+#[test]
+fn reorders_entries() {
+    let (mut app, tray) = mock_app();
+    app.set_menu(items(&["A", "B", "C"])).unwrap();
+    let before = indices(&tray.menu());
+
+    app.set_menu(items(&["C", "A", "B"])).unwrap();
+    let menu = tray.menu();
+    assert_eq!(labels(&menu), vec!["C", "A", "B"]);
+    // A and B stay in place, C moved and is recreated.
+    assert_eq!(menu[1].index, before[0]);
+    assert_eq!(menu[2].index, before[1]);
+    assert!(!before.contains(&menu[0].index));
+}
+
+#[test]
+fn inserts_and_removes_in_the_middle() {
+    let (mut app, tray) = mock_app();
+    app.set_menu(items(&["A", "B", "C"])).unwrap();
+    let before = indices(&tray.menu());
+
+    app.set_menu(items(&["A", "X", "B", "C"])).unwrap();
+    let menu = tray.menu();
+    assert_eq!(labels(&menu), vec!["A", "X", "B", "C"]);
+    assert_eq!(
+        indices(&menu),
+        vec![before[0], menu[1].index, before[1], before[2]]
+    );
+
+    app.set_menu(items(&["A", "C"])).unwrap();
+    let menu = tray.menu();
+    assert_eq!(labels(&menu), vec!["A", "C"]);
+    assert_eq!(indices(&menu), vec![before[0], before[2]]);
+    assert!(tray.menu_item(before[1]).is_none());
+}
+
+#[test]
+fn keeps_entries_with_duplicate_keys() {
+    let (mut app, tray) = mock_app();
+    let clicks = Arc::new(AtomicU32::new(0));
+    let build = |clicks: &Arc<AtomicU32>| {
+        let first = clicks.clone();
+        let second = clicks.clone();
+        Menu::new()
+            .item(MenuItem::new("Same").on_activate(move |_| {
+                first.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, Error>(())
+            }))
+            .item(MenuItem::separator())
+            .item(MenuItem::separator())
+            .item(MenuItem::new("Same").on_activate(move |_| {
+                second.fetch_add(10, Ordering::SeqCst);
+                Ok::<_, Error>(())
+            }))
+    };
+    app.set_menu(build(&clicks)).unwrap();
+    let before = indices(&tray.menu());
+    assert_eq!(before.len(), 4);
+
+    app.set_menu(build(&clicks)).unwrap();
+    assert_eq!(indices(&tray.menu()), before);
+
+    // Each entry keeps its own callback.
+    tray.activate(before[3]).unwrap();
+    app.try_dispatch().unwrap();
+    assert_eq!(clicks.load(Ordering::SeqCst), 10);
+    tray.activate(before[0]).unwrap();
+    app.try_dispatch().unwrap();
+    assert_eq!(clicks.load(Ordering::SeqCst), 11);
+
+    // Dropping one of the duplicates keeps the other.
+    app.set_menu(items(&["Same"])).unwrap();
+    let menu = tray.menu();
+    assert_eq!(menu.len(), 1);
+    assert_eq!(menu[0].index, before[0]);
+}
+
+#[test]
+fn replaces_submenu_with_leaf() {
+    let (mut app, tray) = mock_app();
+    let nested = Menu::new().item(MenuItem::submenu("Deeper", items(&["Leaf"])));
+    app.set_menu(Menu::new().item(MenuItem::submenu("More", nested)))
+        .unwrap();
+    let menu = tray.menu();
+    let submenu = &menu[0];
+    let deeper = &submenu.children[0];
+    let leaf = &deeper.children[0];
+    assert_eq!(submenu.kind, MockItemKind::Submenu);
+    assert_eq!(deeper.kind, MockItemKind::Submenu);
+    assert_eq!(leaf.label, "Leaf");
+
+    app.set_menu(items(&["More"])).unwrap();
+    let menu = tray.menu();
+    assert_eq!(menu.len(), 1);
+    assert_eq!(menu[0].kind, MockItemKind::Item);
+    assert_eq!(menu[0].label, "More");
+    assert!(menu[0].children.is_empty());
+    for idx in &[submenu.index, deeper.index, leaf.index] {
+        assert!(tray.menu_item(*idx).is_none());
+    }
+}
+
+#[test]
+fn updates_submenu_contents_in_place() {
+    let (mut app, tray) = mock_app();
+    app.set_menu(Menu::new().item(MenuItem::submenu("More", items(&["A", "B"]))))
+        .unwrap();
+    let before = tray.menu();
+
+    app.set_menu(Menu::new().item(MenuItem::submenu("More", items(&["B", "C"]))))
+        .unwrap();
+    let menu = tray.menu();
+    assert_eq!(menu[0].index, before[0].index);
+    assert_eq!(labels(&menu[0].children), vec!["B", "C"]);
+    assert_eq!(menu[0].children[0].index, before[0].children[1].index);
+}
+
+#[test]
+fn updates_properties_by_id() {
+    let (mut app, tray) = mock_app();
+    app.set_menu(Menu::new().item(MenuItem::new("Connect").id("conn")))
+        .unwrap();
+    let idx = app.menu_item_by_id("conn").unwrap();
+
+    app.set_menu(
+        Menu::new().item(
+            MenuItem::new("Disconnect")
+                .id("conn")
+                .enabled(false)
+                .visible(false),
+        ),
+    )
+    .unwrap();
+    assert_eq!(app.menu_item_by_id("conn"), Some(idx));
+    let item = tray.menu_item(idx).unwrap();
+    assert_eq!(item.label, "Disconnect");
+    assert!(!item.enabled);
+    assert!(!item.visible);
+}
+
+fn radios(checked: &str) -> Menu {
+    let radio = |label: &str| MenuItem::radio(label, "speed", label == checked);
+    Menu::new()
+        .item(radio("Slow"))
+        .item(MenuItem::submenu("More", Menu::new().item(radio("Fast"))))
+}
+
+#[test]
+fn keeps_radio_group_across_applies() {
+    let (mut app, tray) = mock_app();
+    app.set_menu(radios("Slow")).unwrap();
+    let menu = tray.menu();
+    let slow = menu[0].index;
+    let fast = menu[1].children[0].index;
+    assert!(tray.menu_item(slow).unwrap().checked);
+    assert!(!tray.menu_item(fast).unwrap().checked);
+
+    // The group spans the submenu, a click there unchecks the top level entry.
+    tray.toggle(fast).unwrap();
+    app.try_dispatch().unwrap();
+    assert!(!tray.menu_item(slow).unwrap().checked);
+    assert!(tray.menu_item(fast).unwrap().checked);
+
+    // Applying the state the user picked changes nothing.
+    app.set_menu(radios("Fast")).unwrap();
+    assert_eq!(tray.menu()[0].index, slow);
+    assert!(!tray.menu_item(slow).unwrap().checked);
+    assert!(tray.menu_item(fast).unwrap().checked);
+
+    // The tree wins over the last click.
+    app.set_menu(radios("Slow")).unwrap();
+    assert!(tray.menu_item(slow).unwrap().checked);
+    assert!(!tray.menu_item(fast).unwrap().checked);
+    assert!(app.is_menu_item_checked(slow).unwrap());
+    assert!(!app.is_menu_item_checked(fast).unwrap());
+}
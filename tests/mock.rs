@@ -0,0 +1,245 @@
+// Drives an application through the mock backend, the way a user would.
+
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use systray::{
+    Application, BackendKind, Error, Event, Icon, MockIcon, MockItemKind, MockTray, Notification,
+    Status,
+};
+
+fn mock_app() -> (Application, MockTray) {
+    let app = Application::with_backend(BackendKind::Mock).unwrap();
+    let tray = app.mock().unwrap();
+    (app, tray)
+}
+
+// Icons have no equality, they are told apart by their size.
+fn icon(size: u32) -> Icon {
+    Icon::from_rgba(vec![0; (size * size * 4) as usize], size, size).unwrap()
+}
+
+fn same_icon(a: &Icon, b: &Icon) -> bool {
+    format!("{:?}", a) == format!("{:?}", b)
+}
+
+#[test]
+fn records_menu() {
+    let (mut app, tray) = mock_app();
+    let open = app.add_menu_item("Open", |_| Ok::<_, Error>(())).unwrap();
+    let sep = app.add_menu_separator().unwrap();
+    let submenu = app.add_submenu("More").unwrap();
+    let check = submenu
+        .add_check_menu_item(&mut app, "Sync", true, |_, _| Ok::<_, Error>(()))
+        .unwrap();
+    app.set_menu_item_enabled(open, false).unwrap();
+    app.set_menu_item_label(check, "Sync now").unwrap();
+
+    let menu = tray.menu();
+    assert_eq!(menu.len(), 3);
+    assert_eq!(menu[0].index, open);
+    assert_eq!(menu[0].label, "Open");
+    assert!(!menu[0].enabled);
+    assert_eq!(menu[1].index, sep);
+    assert_eq!(menu[1].kind, MockItemKind::Separator);
+    assert_eq!(menu[2].index, submenu.index());
+    assert_eq!(menu[2].kind, MockItemKind::Submenu);
+    assert_eq!(menu[2].children.len(), 1);
+    let sync = &menu[2].children[0];
+    assert_eq!(sync.kind, MockItemKind::Check);
+    assert_eq!(sync.label, "Sync now");
+    assert!(sync.checked);
+
+    app.remove_menu_item(submenu.index()).unwrap();
+    assert_eq!(tray.menu().len(), 2);
+    assert!(tray.menu_item(check).is_none());
+}
+
+#[test]
+fn activate_runs_callback() {
+    let (mut app, tray) = mock_app();
+    let clicks = Arc::new(AtomicU32::new(0));
+    let counter = clicks.clone();
+    let idx = app
+        .add_menu_item("Count", move |app| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            app.set_tooltip("Counted")
+        })
+        .unwrap();
+
+    tray.activate(idx).unwrap();
+    tray.activate(idx).unwrap();
+    let events = app.try_dispatch().unwrap();
+    assert_eq!(
+        events,
+        vec![
+            Event::MenuItemActivated { index: idx },
+            Event::MenuItemActivated { index: idx }
+        ]
+    );
+    assert_eq!(clicks.load(Ordering::SeqCst), 2);
+    assert_eq!(tray.tooltip(), "Counted");
+}
+
+#[test]
+fn activate_rejects_unclickable_entries() {
+    let (mut app, tray) = mock_app();
+    let disabled = app
+        .add_menu_item("Disabled", |_| Ok::<_, Error>(()))
+        .unwrap();
+    app.set_menu_item_enabled(disabled, false).unwrap();
+    let hidden = app.add_menu_item("Hidden", |_| Ok::<_, Error>(())).unwrap();
+    app.set_menu_item_visible(hidden, false).unwrap();
+    let sep = app.add_menu_separator().unwrap();
+    let submenu = app.add_submenu("More").unwrap();
+    let plain = app.add_menu_item("Plain", |_| Ok::<_, Error>(())).unwrap();
+
+    for idx in &[disabled, hidden, sep, submenu.index(), 1000] {
+        assert!(matches!(tray.activate(*idx), Err(Error::InvalidMenuItem(i)) if i == *idx));
+    }
+    assert!(tray.toggle(plain).is_err());
+    assert!(app.try_dispatch().unwrap().is_empty());
+}
+
+#[test]
+fn toggle_check_item() {
+    let (mut app, tray) = mock_app();
+    let seen = Arc::new(AtomicU32::new(0));
+    let last = seen.clone();
+    let idx = app
+        .add_check_menu_item("Mute", false, move |_, checked| {
+            last.store(checked as u32 + 1, Ordering::SeqCst);
+            Ok::<_, Error>(())
+        })
+        .unwrap();
+
+    tray.toggle(idx).unwrap();
+    assert_eq!(
+        app.try_dispatch().unwrap(),
+        vec![Event::CheckToggled {
+            index: idx,
+            checked: true
+        }]
+    );
+    assert_eq!(seen.load(Ordering::SeqCst), 2);
+    assert!(tray.menu_item(idx).unwrap().checked);
+    assert!(app.is_menu_item_checked(idx).unwrap());
+
+    tray.toggle(idx).unwrap();
+    app.try_dispatch().unwrap();
+    assert_eq!(seen.load(Ordering::SeqCst), 1);
+    assert!(!tray.menu_item(idx).unwrap().checked);
+}
+
+#[test]
+fn toggle_radio_group() {
+    let (mut app, tray) = mock_app();
+    let group = app.add_radio_group();
+    let low = app
+        .add_radio_menu_item(group, "Low", true, |_, _| Ok::<_, Error>(()))
+        .unwrap();
+    let high = app
+        .add_radio_menu_item(group, "High", false, |_, _| Ok::<_, Error>(()))
+        .unwrap();
+
+    tray.toggle(high).unwrap();
+    app.try_dispatch().unwrap();
+    assert!(!tray.menu_item(low).unwrap().checked);
+    assert!(tray.menu_item(high).unwrap().checked);
+    assert!(!app.is_menu_item_checked(low).unwrap());
+}
+
+#[test]
+fn send_event_passes_through() {
+    let (mut app, tray) = mock_app();
+    tray.send_event(Event::LeftClick);
+    tray.send_event(Event::TrayHostChanged { available: false });
+    assert_eq!(
+        app.dispatch_timeout(Duration::from_secs(1)).unwrap(),
+        vec![
+            Event::LeftClick,
+            Event::TrayHostChanged { available: false }
+        ]
+    );
+}
+
+#[test]
+fn records_icon_tooltip_and_status() {
+    let app = Application::builder()
+        .backend(BackendKind::Mock)
+        .title("Title")
+        .icon_name("start")
+        .build()
+        .unwrap();
+    let tray = app.mock().unwrap();
+    assert_eq!(tray.tooltip(), "Title");
+    assert!(matches!(tray.icon(), MockIcon::Name(ref n) if n == "start"));
+
+    app.set_icon_from_file("/tmp/icon.png").unwrap();
+    assert!(matches!(tray.icon(), MockIcon::File(ref f) if f == "/tmp/icon.png"));
+    app.set_icon(&icon(1)).unwrap();
+    assert!(matches!(tray.icon(), MockIcon::Image(ref i) if same_icon(i, &icon(1))));
+
+    let frames = vec![icon(1), icon(2)];
+    app.set_animated_icon(frames.clone(), Duration::from_millis(50))
+        .unwrap();
+    match tray.icon() {
+        MockIcon::Animated(f, d) => {
+            assert_eq!(f.len(), 2);
+            assert!(same_icon(&f[0], &frames[0]) && same_icon(&f[1], &frames[1]));
+            assert_eq!(d, Duration::from_millis(50));
+        }
+        other => panic!("unexpected icon {:?}", other),
+    }
+    app.stop_animation().unwrap();
+    assert!(matches!(tray.icon(), MockIcon::Image(ref i) if same_icon(i, &icon(1))));
+
+    app.set_tooltip("Busy").unwrap();
+    app.set_label("3", "99").unwrap();
+    app.set_attention_icon(&icon(3)).unwrap();
+    app.set_status(Status::NeedsAttention).unwrap();
+    app.set_icon_theme_path("/tmp/icons").unwrap();
+    assert_eq!(tray.tooltip(), "Busy");
+    assert_eq!(tray.label(), ("3".to_owned(), "99".to_owned()));
+    assert!(same_icon(&tray.attention_icon().unwrap(), &icon(3)));
+    assert_eq!(tray.status(), Status::NeedsAttention);
+    assert_eq!(tray.icon_theme_path(), Some("/tmp/icons".to_owned()));
+
+    // The handle changes the same tray.
+    app.handle().set_tooltip("From handle").unwrap();
+    assert_eq!(tray.tooltip(), "From handle");
+}
+
+#[test]
+fn records_notifications() {
+    let (app, tray) = mock_app();
+    let first = app.notify(Notification::new("One", "First")).unwrap();
+    let second = app.notify(Notification::new("Two", "Second")).unwrap();
+    assert_eq!((first, second), (1, 2));
+    let shown = tray.notifications();
+    assert_eq!(shown.len(), 2);
+    assert_eq!(shown[1].title, "Two");
+    assert_eq!(shown[1].body, "Second");
+}
+
+#[test]
+fn quit_from_menu() {
+    let (mut app, tray) = mock_app();
+    let idx = app
+        .add_menu_item("Quit", |app| {
+            app.quit();
+            Ok::<_, Error>(())
+        })
+        .unwrap();
+    tray.activate(idx).unwrap();
+    let events = app.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(
+        events,
+        vec![Event::MenuItemActivated { index: idx }, Event::Shutdown]
+    );
+    assert!(tray.is_quit());
+}
@@ -0,0 +1,165 @@
+// Runs the StatusNotifierItem backend on a private session bus, with a stub
+// watcher standing in for the panel. Skipped when dbus-daemon is not
+// installed.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    env,
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc::{channel, Sender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use systray::{Application, BackendKind, Error, Event};
+use zbus::{
+    blocking::{connection, Connection},
+    interface,
+    zvariant::{OwnedValue, Value},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Bus(Child);
+
+impl Bus {
+    // Starts a session bus and points the backends at it.
+    fn start() -> Option<Bus> {
+        let child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Cannot run dbus-daemon, skipping: {}", e);
+                return None;
+            }
+        };
+        let mut address = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        env::set_var("DBUS_SESSION_BUS_ADDRESS", address.trim());
+        Some(Bus(child))
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+// Hands the names of the items registering to the test.
+struct Watcher {
+    items: Mutex<Sender<String>>,
+}
+
+#[interface(name = "org.kde.StatusNotifierWatcher")]
+impl Watcher {
+    fn register_status_notifier_item(&self, service: String) {
+        self.items.lock().unwrap().send(service).ok();
+    }
+
+    #[zbus(property)]
+    fn is_status_notifier_host_registered(&self) -> bool {
+        true
+    }
+}
+
+// Id and label of an entry in a GetLayout reply.
+fn entry(value: &OwnedValue) -> (i32, String) {
+    let value = match &**value {
+        Value::Value(v) => &**v,
+        v => v,
+    };
+    let fields = match value {
+        Value::Structure(s) => s.fields(),
+        v => panic!("unexpected layout entry {:?}", v),
+    };
+    let id = i32::try_from(&fields[0]).unwrap();
+    let props = HashMap::<String, OwnedValue>::try_from(fields[1].try_clone().unwrap()).unwrap();
+    let label = <&str>::try_from(&props["label"]).unwrap().to_owned();
+    (id, label)
+}
+
+fn name_has_owner(conn: &Connection, name: &str) -> bool {
+    conn.call_method(
+        Some("org.freedesktop.DBus"),
+        "/org/freedesktop/DBus",
+        Some("org.freedesktop.DBus"),
+        "NameHasOwner",
+        &name,
+    )
+    .and_then(|m| m.body().deserialize::<bool>())
+    .unwrap()
+}
+
+#[test]
+fn registers_and_reports_menu_clicks() {
+    let _bus = match Bus::start() {
+        Some(bus) => bus,
+        None => return,
+    };
+    let (items_tx, items_rx) = channel();
+    let watcher = Watcher {
+        items: Mutex::new(items_tx),
+    };
+    let _watcher = connection::Builder::session()
+        .and_then(|b| b.name("org.kde.StatusNotifierWatcher"))
+        .and_then(|b| b.serve_at("/StatusNotifierWatcher", watcher))
+        .and_then(|b| b.build())
+        .unwrap();
+
+    let mut app = Application::with_backend(BackendKind::StatusNotifierItem).unwrap();
+    let item = app.add_menu_item("Item", |_| Ok::<_, Error>(())).unwrap();
+    let name = items_rx.recv_timeout(TIMEOUT).unwrap();
+    assert!(name.starts_with("org.kde.StatusNotifierItem-"));
+
+    // The host reads the menu the way panels do.
+    let conn = Connection::session().unwrap();
+    let reply = conn
+        .call_method(
+            Some(name.as_str()),
+            "/MenuBar",
+            Some("com.canonical.dbusmenu"),
+            "GetLayout",
+            &(0i32, -1i32, Vec::<&str>::new()),
+        )
+        .unwrap();
+    let (_, (root, _, children)) = reply
+        .body()
+        .deserialize::<(u32, (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>))>()
+        .unwrap();
+    assert_eq!(root, 0);
+    assert_eq!(children.len(), 1);
+    let (id, label) = entry(&children[0]);
+    assert_eq!(label, "Item");
+
+    conn.call_method(
+        Some(name.as_str()),
+        "/MenuBar",
+        Some("com.canonical.dbusmenu"),
+        "Event",
+        &(id, "clicked", Value::from(0i32), 0u32),
+    )
+    .unwrap();
+    assert_eq!(
+        app.dispatch_timeout(TIMEOUT).unwrap(),
+        vec![Event::MenuItemActivated { index: item }]
+    );
+
+    // Dropping the application takes the item off the bus.
+    drop(app);
+    let deadline = Instant::now() + TIMEOUT;
+    while name_has_owner(&conn, &name) {
+        assert!(Instant::now() < deadline, "{} is still on the bus", name);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
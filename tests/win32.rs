@@ -0,0 +1,41 @@
+// Changes entries of the Windows tray menu while they are hidden.
+#![cfg(target_os = "windows")]
+
+use systray::{Application, BackendKind, Error};
+
+#[test]
+fn updates_children_of_hidden_submenu() {
+    let mut app = Application::with_backend(BackendKind::Win32).unwrap();
+    let submenu = app.add_submenu("More").unwrap();
+    let check = submenu
+        .add_check_menu_item(&mut app, "Sync", false, |_, _| Ok::<_, Error>(()))
+        .unwrap();
+    let group = app.add_radio_group();
+    let low = submenu
+        .add_radio_menu_item(&mut app, group, "Low", true, |_, _| Ok::<_, Error>(()))
+        .unwrap();
+    let high = submenu
+        .add_radio_menu_item(&mut app, group, "High", false, |_, _| Ok::<_, Error>(()))
+        .unwrap();
+    app.set_menu_item_visible(submenu.index(), false).unwrap();
+
+    // The submenu is out of the menu tree, its entries are still there.
+    app.set_menu_item_checked(check, true).unwrap();
+    app.set_menu_item_enabled(check, false).unwrap();
+    app.set_menu_item_label(check, "Sync now").unwrap();
+    app.set_menu_item_checked(high, true).unwrap();
+    assert!(!app.is_menu_item_checked(low).unwrap());
+    assert!(app.is_menu_item_checked(high).unwrap());
+
+    app.set_menu_item_visible(submenu.index(), true).unwrap();
+    app.set_menu_item_checked(low, true).unwrap();
+    app.set_menu_item_enabled(check, true).unwrap();
+    assert!(!app.is_menu_item_checked(high).unwrap());
+
+    // Handles look the entries up the same way.
+    app.set_menu_item_visible(submenu.index(), false).unwrap();
+    let handle = app.handle();
+    handle.set_menu_item_enabled(check, false).unwrap();
+    handle.set_menu_item_label(check, "Sync later").unwrap();
+    app.quit();
+}
@@ -0,0 +1,199 @@
+// Runs the XEmbed backend against a minimal system tray. Needs an X server
+// without a tray, such as Xvfb, and is skipped when DISPLAY is not set:
+//
+//     Xvfb :5 &
+//     DISPLAY=:5 cargo test --no-default-features --features xembed --test xembed
+
+use std::{
+    env, thread,
+    time::{Duration, Instant},
+};
+use systray::{Application, BackendKind, Error, Event};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        xproto::{
+            ButtonPressEvent, ConfigureWindowAux, ConnectionExt, CreateWindowAux, EventMask,
+            Window, WindowClass, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT,
+        },
+        Event as XEvent,
+    },
+    rust_connection::RustConnection,
+    COPY_FROM_PARENT, CURRENT_TIME, NONE,
+};
+
+const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+const ICON_SIZE: u16 = 24;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Host {
+    conn: RustConnection,
+    root: Window,
+    bar: Window,
+    opcode: u32,
+}
+
+impl Host {
+    // Takes the tray selection, unless there is no X server or a tray
+    // already runs on it.
+    fn start() -> Option<Host> {
+        if env::var_os("DISPLAY").is_none() {
+            eprintln!("DISPLAY is not set, skipping");
+            return None;
+        }
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let screen = conn.setup().roots[screen_num].clone();
+        let intern = |name: &str| {
+            conn.intern_atom(false, name.as_bytes())
+                .unwrap()
+                .reply()
+                .unwrap()
+                .atom
+        };
+        let selection = intern(&format!("_NET_SYSTEM_TRAY_S{}", screen_num));
+        let opcode = intern("_NET_SYSTEM_TRAY_OPCODE");
+        if conn
+            .get_selection_owner(selection)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .owner
+            != NONE
+        {
+            eprintln!("A system tray already runs, skipping");
+            return None;
+        }
+        let bar = conn.generate_id().unwrap();
+        conn.create_window(
+            COPY_FROM_PARENT as u8,
+            bar,
+            screen.root,
+            0,
+            0,
+            ICON_SIZE,
+            ICON_SIZE,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            screen.root_visual,
+            &CreateWindowAux::new().event_mask(EventMask::SUBSTRUCTURE_NOTIFY),
+        )
+        .unwrap();
+        conn.map_window(bar).unwrap();
+        conn.set_selection_owner(bar, selection, CURRENT_TIME)
+            .unwrap();
+        conn.flush().unwrap();
+        Some(Host {
+            conn,
+            root: screen.root,
+            bar,
+            opcode,
+        })
+    }
+
+    // Waits for a dock request and embeds the icon window it names.
+    fn dock(&self) -> Window {
+        let deadline = Instant::now() + TIMEOUT;
+        let icon = loop {
+            assert!(Instant::now() < deadline, "No dock request");
+            match self.conn.poll_for_event().unwrap() {
+                Some(XEvent::ClientMessage(e))
+                    if e.window == self.bar
+                        && e.type_ == self.opcode
+                        && e.data.as_data32()[1] == SYSTEM_TRAY_REQUEST_DOCK =>
+                {
+                    break e.data.as_data32()[2];
+                }
+                Some(_) => {}
+                None => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        self.conn.reparent_window(icon, self.bar, 0, 0).unwrap();
+        self.conn
+            .configure_window(
+                icon,
+                &ConfigureWindowAux::new()
+                    .width(u32::from(ICON_SIZE))
+                    .height(u32::from(ICON_SIZE)),
+            )
+            .unwrap();
+        self.conn.map_window(icon).unwrap();
+        self.conn.flush().unwrap();
+        icon
+    }
+
+    // Sends a press and release of `button` at root coordinates `x`, `y`.
+    fn click(&self, icon: Window, button: u8, x: i16, y: i16) {
+        for &response_type in &[BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT] {
+            let event = ButtonPressEvent {
+                response_type,
+                detail: button,
+                sequence: 0,
+                time: 0,
+                root: self.root,
+                event: icon,
+                child: NONE,
+                root_x: x,
+                root_y: y,
+                event_x: x,
+                event_y: y,
+                state: Default::default(),
+                same_screen: true,
+            };
+            self.conn
+                .send_event(false, icon, EventMask::NO_EVENT, event)
+                .unwrap();
+        }
+        self.conn.flush().unwrap();
+    }
+}
+
+// Dispatches events until all of `wanted` came in, in order.
+fn expect(app: &mut Application, wanted: &[Event]) {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut seen = vec![];
+    let mut left = wanted.iter().peekable();
+    while left.peek().is_some() {
+        assert!(
+            Instant::now() < deadline,
+            "Expected {:?}, got {:?}",
+            wanted,
+            seen
+        );
+        for event in app.dispatch_timeout(Duration::from_millis(100)).unwrap() {
+            if left.peek() == Some(&&event) {
+                left.next();
+            }
+            seen.push(event);
+        }
+    }
+}
+
+#[test]
+fn docks_and_reports_clicks() {
+    let host = match Host::start() {
+        Some(host) => host,
+        None => return,
+    };
+    let mut app = Application::with_backend(BackendKind::XEmbed).unwrap();
+    let icon = host.dock();
+    let item = app.add_menu_item("Item", |_| Ok::<_, Error>(())).unwrap();
+
+    // A left click reports the click and opens the menu.
+    host.click(icon, 1, 5, 5);
+    expect(&mut app, &[Event::LeftClick, Event::MenuOpened]);
+
+    // The menu opens next to the pointer, its first row is just below it.
+    host.click(icon, 1, 20, 12);
+    expect(
+        &mut app,
+        &[Event::MenuClosed, Event::MenuItemActivated { index: item }],
+    );
+
+    host.click(icon, 2, 5, 5);
+    expect(&mut app, &[Event::MiddleClick]);
+
+    // The tray going away is reported.
+    host.conn.destroy_window(host.bar).unwrap();
+    host.conn.flush().unwrap();
+    expect(&mut app, &[Event::TrayHostChanged { available: false }]);
+}